@@ -0,0 +1,295 @@
+use crate::auth::{AclAction, Permission};
+use crate::broker::{covers, matches, valid_level};
+use crate::config::ConfigError;
+use config::File;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use tokio::time::{Duration, Instant};
+
+// Authorization configuration
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Acl {
+    // Check the rules file, rules granted by authenticators always apply
+    pub enable: bool,
+    pub file: String,
+    // Permission when no rule matches
+    pub no_match: Permission,
+    pub deny_action: DenyAction,
+    // Results cached per session, 0 disables the cache
+    pub cache_max_size: usize,
+    // Seconds a cached result is used
+    pub cache_ttl: u64,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            file: "./config/acl.toml".to_string(),
+            no_match: Permission::Allow,
+            deny_action: DenyAction::Ignore,
+            cache_max_size: 32,
+            cache_ttl: 60,
+        }
+    }
+}
+
+// What happens to a client whose PUBLISH or SUBSCRIBE is denied
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DenyAction {
+    // Reject the packet with Not Authorized
+    Ignore,
+    // Close the connection
+    Disconnect,
+}
+
+// IP network in CIDR notation, a plain address is a single host
+#[derive(Debug, Clone, Copy)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid ip address or cidr {:?}", s);
+        let (addr, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let addr = addr.parse::<IpAddr>().map_err(|_| invalid())?.to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => max,
+            prefix => prefix.parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(invalid)?,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// Clients a rule applies to, every given field must match, none means all clients
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Who {
+    pub username: Option<String>,
+    pub clientid: Option<String>,
+    pub ipaddr: Option<Cidr>,
+}
+
+// Client identity rules are matched against
+pub struct Client<'a> {
+    pub client_id: &'a str,
+    pub username: Option<&'a str>,
+    pub ip: IpAddr,
+}
+
+impl Who {
+    fn matches(&self, client: &Client) -> bool {
+        self.username.as_ref().is_none_or(|u| client.username == Some(u.as_str()))
+            && self.clientid.as_ref().is_none_or(|c| client.client_id == c)
+            && self.ipaddr.is_none_or(|cidr| cidr.contains(client.ip))
+    }
+}
+
+// ACL rule, topics support ${clientid} / ${username} and "eq <topic>" for an exact match
+#[derive(Debug, Deserialize, Clone)]
+pub struct Rule {
+    pub permission: Permission,
+    #[serde(default)]
+    pub who: Who,
+    #[serde(default = "Rule::all")]
+    pub action: AclAction,
+    pub topics: Vec<String>,
+}
+
+impl Rule {
+    fn all() -> AclAction {
+        AclAction::All
+    }
+
+    fn matches(&self, client: &Client, action: AclAction, topic: &str) -> bool {
+        (self.action == action || self.action == AclAction::All)
+            && self.who.matches(client)
+            && self.topics.iter().any(|t| Self::topic_matches(t, client, action, topic))
+    }
+
+    // Subscriptions must be within the rule filter, published topics matched by it
+    fn topic_matches(rule: &str, client: &Client, action: AclAction, topic: &str) -> bool {
+        if let Some(eq) = rule.strip_prefix("eq ") {
+            return eq == topic;
+        }
+        let mut filter = rule.to_string();
+        if filter.contains("${clientid}") {
+            if !valid_level(client.client_id) {
+                return false;
+            }
+            filter = filter.replace("${clientid}", client.client_id);
+        }
+        if filter.contains("${username}") {
+            let Some(username) = client.username.filter(|u| valid_level(u)) else {
+                return false;
+            };
+            filter = filter.replace("${username}", username);
+        }
+        match action {
+            AclAction::Subscribe => covers(&filter, topic),
+            _ => matches(&filter, topic),
+        }
+    }
+}
+
+// Ordered rules file, the first matching rule decides
+#[derive(Debug, Deserialize, Default)]
+pub struct Rules {
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        if !Path::new(path).exists() {
+            return Err(ConfigError::NotFound(path.to_string()));
+        }
+        let cfg = config::Config::builder().add_source(File::with_name(path)).build()?;
+        cfg.try_deserialize().map_err(|e| e.into())
+    }
+
+    pub fn check(&self, client: &Client, action: AclAction, topic: &str) -> Option<Permission> {
+        self.rules.iter().find(|rule| rule.matches(client, action, topic)).map(|r| r.permission)
+    }
+}
+
+// Per session cache of authorization results
+#[derive(Default)]
+pub struct AclCache {
+    entries: HashMap<(AclAction, String), (Instant, Permission)>,
+}
+
+impl AclCache {
+    pub fn get(&self, action: AclAction, topic: &str) -> Option<Permission> {
+        let (expire, permission) = self.entries.get(&(action, topic.to_string()))?;
+        (*expire > Instant::now()).then_some(*permission)
+    }
+
+    pub fn insert(&mut self, cfg: &Acl, action: AclAction, topic: &str, permission: Permission) {
+        if cfg.cache_max_size == 0 {
+            return;
+        }
+        let now = Instant::now();
+        if self.entries.len() >= cfg.cache_max_size {
+            self.entries.retain(|_, (expire, _)| *expire > now);
+        }
+        if self.entries.len() < cfg.cache_max_size {
+            let expire = now + Duration::from_secs(cfg.cache_ttl);
+            self.entries.insert((action, topic.to_string()), (expire, permission));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(permission: Permission, who: Who, action: AclAction, topics: &[&str]) -> Rule {
+        Rule { permission, who, action, topics: topics.iter().map(|t| t.to_string()).collect() }
+    }
+
+    #[test]
+    fn test_check() {
+        use AclAction::*;
+        use Permission::*;
+
+        let local = Who { ipaddr: Some("127.0.0.0/8".parse().unwrap()), ..Default::default() };
+        let admin = Who { username: Some("admin".into()), ..Default::default() };
+        let rules = Rules {
+            rules: vec![
+                rule(Allow, admin, Subscribe, &["$SYS/#"]),
+                rule(Allow, local, All, &["$SYS/#", "#"]),
+                rule(Deny, Who::default(), Subscribe, &["$SYS/#", "eq #"]),
+                rule(Allow, Who::default(), All, &["devices/${clientid}/#", "users/${username}"]),
+                rule(Deny, Who::default(), All, &["#"]),
+            ],
+        };
+
+        let remote = "10.0.0.1".parse().unwrap();
+        let client = |username, ip| Client { client_id: "c1", username, ip };
+        let check = |client: &Client, action, topic| rules.check(client, action, topic);
+
+        let c = client(Some("admin"), remote);
+        assert_eq!(check(&c, Subscribe, "$SYS/brokers"), Some(Allow));
+        // # does not match $ topics, left to no_match
+        assert_eq!(check(&c, Publish, "$SYS/brokers"), None);
+
+        // v4 mapped v6 addresses match v4 networks
+        let c = client(None, "::ffff:127.0.0.1".parse().unwrap());
+        assert_eq!(check(&c, Subscribe, "#"), Some(Allow));
+
+        let c = client(None, remote);
+        assert_eq!(check(&c, Subscribe, "#"), Some(Deny));
+        assert_eq!(check(&c, Subscribe, "$SYS/#"), Some(Deny));
+        assert_eq!(check(&c, Publish, "devices/c1/up"), Some(Allow));
+        assert_eq!(check(&c, Publish, "devices/c2/up"), Some(Deny));
+        assert_eq!(check(&c, Publish, "users/"), Some(Deny));
+
+        let c = client(Some("u1"), remote);
+        assert_eq!(check(&c, Subscribe, "users/u1"), Some(Allow));
+
+        // Subscriptions wider than the granted filter fall through to the deny
+        let narrow = Rules {
+            rules: vec![
+                rule(Allow, Who::default(), Subscribe, &["devices/${clientid}/+", "+"]),
+                rule(Deny, Who::default(), All, &["#"]),
+            ],
+        };
+        let subscribe = |filter| narrow.check(&c, Subscribe, filter);
+        assert_eq!(subscribe("devices/c1/up"), Some(Allow));
+        assert_eq!(subscribe("devices/c1/+"), Some(Allow));
+        assert_eq!(subscribe("devices/c1/#"), Some(Deny));
+        assert_eq!(subscribe("devices/+/up"), Some(Deny));
+        assert_eq!(subscribe("a"), Some(Allow));
+        assert_eq!(subscribe("#"), Some(Deny));
+
+        // Placeholder values with wildcards or separators never match
+        let c = Client { client_id: "#", username: Some("+"), ip: remote };
+        assert_eq!(check(&c, Subscribe, "devices/c2/up"), Some(Deny));
+        assert_eq!(check(&c, Subscribe, "users/u1"), Some(Deny));
+        let c = Client { client_id: "c1/x", username: Some("u1/x"), ip: remote };
+        assert_eq!(check(&c, Publish, "devices/c1/x/up"), Some(Deny));
+        assert_eq!(check(&c, Publish, "users/u1/x"), Some(Deny));
+    }
+
+    #[test]
+    fn test_cidr() {
+        let cidr: Cidr = "192.168.1.0/24".parse().unwrap();
+        assert!(cidr.contains("192.168.1.77".parse().unwrap()));
+        assert!(!cidr.contains("192.168.2.1".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!("::1".parse::<Cidr>().unwrap().contains("::1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    }
+}
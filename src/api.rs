@@ -1,10 +1,19 @@
-use axum::extract::OriginalUri;
+use crate::auth::users::UserInfo;
+use crate::banned::{Ban, BanAs};
+use crate::Context;
+use axum::extract::{ConnectInfo, OriginalUri, Path, Request};
+use axum::http::header::AUTHORIZATION;
 use axum::http::StatusCode;
+use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Response};
-use axum::routing::get;
-use axum::{Json, Router};
-use serde::Serialize;
+use axum::routing::{delete, get};
+use axum::{Extension, Json, Router};
+use chrono::{DateTime, Local};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use tokio::task::spawn_blocking;
 
 #[derive(thiserror::Error, Debug)]
 enum ApiError {
@@ -12,12 +21,15 @@ enum ApiError {
     Error { code: u16, msg: String },
     #[error("Api not found: {0}")]
     NotFound(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
 }
 
 impl ApiError {
     fn code(&self) -> u16 {
         match self {
             Self::NotFound(_) => 404,
+            Self::Unauthorized(_) => 401,
             Self::Error { code, .. } => *code,
         }
     }
@@ -34,6 +46,7 @@ impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         let status = match self {
             Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             _ => StatusCode::OK,
         };
 
@@ -55,10 +68,6 @@ impl ApiOk<()> {
     fn ok() -> Self {
         Self { code: 0, msg: None, data: None }
     }
-
-    fn msg<M: Into<String>>(msg: M) -> Self {
-        Self { code: 0, msg: Some(msg.into()), data: None }
-    }
 }
 
 impl<T> ApiOk<T> {
@@ -74,7 +83,44 @@ impl<T: Serialize> IntoResponse for ApiOk<T> {
 }
 
 pub fn routes() -> Router {
-    Router::new().route("/version", get(version)).fallback(not_found)
+    // Routes showing or changing broker state need the api key
+    let admin = Router::new()
+        .route("/delayed", get(delayed_list))
+        .route("/delayed/{id}", delete(delayed_delete))
+        .route("/users", get(users_list).post(users_add))
+        .route("/users/{username}", delete(users_delete))
+        .route("/banned", get(banned_list).post(banned_add))
+        .route("/banned/{as}/{*who}", delete(banned_delete))
+        .route("/listeners", get(listeners_list))
+        .route_layer(middleware::from_fn(authorize));
+
+    Router::new().route("/version", get(version)).merge(admin).fallback(not_found)
+}
+
+// Check the bearer token against the api key, without a key only loopback clients are served
+async fn authorize(
+    Extension(ctx): Extension<Context>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let api_key = ctx.config().await.web.api_key;
+    let token = request.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok());
+    if !allowed(api_key.as_deref(), token, peer.ip()) {
+        let reason =
+            if api_key.is_some() { "missing or wrong api key" } else { "not a loopback client" };
+        return Err(ApiError::Unauthorized(reason.to_string()));
+    }
+    Ok(next.run(request).await)
+}
+
+fn allowed(api_key: Option<&str>, authorization: Option<&str>, peer: IpAddr) -> bool {
+    let Some(api_key) = api_key else {
+        return peer.to_canonical().is_loopback();
+    };
+    // Digests are compared so the time taken says nothing about the key
+    let token = authorization.and_then(|value| value.strip_prefix("Bearer ")).unwrap_or_default();
+    digest(&SHA256, token.as_bytes()).as_ref() == digest(&SHA256, api_key.as_bytes()).as_ref()
 }
 
 // 404 Not Found
@@ -85,3 +131,154 @@ async fn not_found(uri: OriginalUri) -> impl IntoResponse {
 async fn version() -> impl IntoResponse {
     ApiOk::data(env!("CARGO_PKG_VERSION"))
 }
+
+// Pending delayed messages
+async fn delayed_list(Extension(ctx): Extension<Context>) -> impl IntoResponse {
+    ApiOk::data(ctx.delayed().list().await)
+}
+
+// Delete a pending delayed message
+async fn delayed_delete(
+    Extension(ctx): Extension<Context>,
+    Path(id): Path<u64>,
+) -> Result<ApiOk<()>, ApiError> {
+    if ctx.delayed().delete(id).await {
+        Ok(ApiOk::ok())
+    } else {
+        Err(ApiError::Error { code: 404, msg: format!("delayed message {} not found", id) })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NewUser {
+    username: String,
+    password: String,
+    #[serde(default)]
+    is_superuser: bool,
+}
+
+// Built-in users
+async fn users_list(Extension(ctx): Extension<Context>) -> Result<ApiOk<Vec<UserInfo>>, ApiError> {
+    // Listing re-reads the file when it changed
+    match spawn_blocking(move || ctx.users().list()).await {
+        Ok(users) => Ok(ApiOk::data(users)),
+        Err(e) => Err(ApiError::Error { code: 500, msg: e.to_string() }),
+    }
+}
+
+// Add a built-in user or replace its password
+async fn users_add(
+    Extension(ctx): Extension<Context>,
+    Json(user): Json<NewUser>,
+) -> Result<ApiOk<()>, ApiError> {
+    // PBKDF2 and the file write block
+    let result =
+        spawn_blocking(move || ctx.users().add(&user.username, &user.password, user.is_superuser))
+            .await;
+    match result {
+        Ok(Ok(())) => Ok(ApiOk::ok()),
+        Ok(Err(e)) => Err(ApiError::Error { code: 400, msg: e.to_string() }),
+        Err(e) => Err(ApiError::Error { code: 500, msg: e.to_string() }),
+    }
+}
+
+// Delete a built-in user
+async fn users_delete(
+    Extension(ctx): Extension<Context>,
+    Path(username): Path<String>,
+) -> Result<ApiOk<()>, ApiError> {
+    let deleted = {
+        let username = username.clone();
+        spawn_blocking(move || ctx.users().delete(&username)).await
+    };
+    match deleted {
+        Ok(Ok(true)) => Ok(ApiOk::ok()),
+        Ok(Ok(false)) => {
+            Err(ApiError::Error { code: 404, msg: format!("user {} not found", username) })
+        }
+        Ok(Err(e)) => Err(ApiError::Error { code: 500, msg: e.to_string() }),
+        Err(e) => Err(ApiError::Error { code: 500, msg: e.to_string() }),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NewBan {
+    #[serde(rename = "as")]
+    kind: BanAs,
+    who: String,
+    #[serde(default)]
+    reason: String,
+    // RFC 3339 time the ban expires, none bans until deleted
+    #[serde(default)]
+    until: Option<DateTime<Local>>,
+}
+
+// Bans in effect
+async fn banned_list(Extension(ctx): Extension<Context>) -> impl IntoResponse {
+    ApiOk::data(ctx.banned().list())
+}
+
+// Ban a client id, username or peer address range
+async fn banned_add(
+    Extension(ctx): Extension<Context>,
+    Json(ban): Json<NewBan>,
+) -> Result<ApiOk<()>, ApiError> {
+    let ban = Ban {
+        kind: ban.kind,
+        who: ban.who,
+        reason: ban.reason,
+        at: Local::now(),
+        until: ban.until,
+    };
+    match spawn_blocking(move || ctx.banned().add(ban)).await {
+        Ok(Ok(())) => Ok(ApiOk::ok()),
+        Ok(Err(e)) => Err(ApiError::Error { code: 400, msg: e.to_string() }),
+        Err(e) => Err(ApiError::Error { code: 500, msg: e.to_string() }),
+    }
+}
+
+// Lift a ban, peerhost ranges keep their slash, /banned/peerhost/10.0.0.0/8
+async fn banned_delete(
+    Extension(ctx): Extension<Context>,
+    Path((kind, who)): Path<(BanAs, String)>,
+) -> Result<ApiOk<()>, ApiError> {
+    let deleted = {
+        let who = who.clone();
+        spawn_blocking(move || ctx.banned().delete(kind, &who)).await
+    };
+    match deleted {
+        Ok(Ok(true)) => Ok(ApiOk::ok()),
+        Ok(Ok(false)) => {
+            Err(ApiError::Error { code: 404, msg: format!("ban of {} not found", who) })
+        }
+        Ok(Err(e)) => Err(ApiError::Error { code: 500, msg: e.to_string() }),
+        Err(e) => Err(ApiError::Error { code: 500, msg: e.to_string() }),
+    }
+}
+
+// Live and maximum connection counts per listener
+async fn listeners_list(Extension(ctx): Extension<Context>) -> impl IntoResponse {
+    ApiOk::data(ctx.connections().stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed() {
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let remote: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // Without a key only loopback clients, v4 mapped included
+        assert!(allowed(None, None, local));
+        assert!(allowed(None, None, "::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!allowed(None, Some("Bearer x"), remote));
+
+        let key = Some("s3cret");
+        assert!(allowed(key, Some("Bearer s3cret"), remote));
+        assert!(!allowed(key, Some("Bearer s3cre"), remote));
+        assert!(!allowed(key, Some("s3cret"), remote));
+        assert!(!allowed(key, None, local));
+    }
+}
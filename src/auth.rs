@@ -0,0 +1,367 @@
+pub mod jwt;
+pub mod scram;
+pub mod users;
+
+use crate::broker::{covers, matches};
+use crate::config::CertField;
+use crate::plugins::Plugins;
+use async_trait::async_trait;
+use jwt::{Jwt, JwtConfig};
+use scram::Scram;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::debug;
+use users::{Users, UsersConfig};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+// Authentication configuration
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Auth {
+    // Allow clients no authenticator in the chain made a decision for
+    pub allow_anonymous: bool,
+    // Authenticators tried in order, disabled ones are skipped
+    pub chain: Vec<Source>,
+    // Built-in user database
+    pub file: UsersConfig,
+    // JWT in the password or username
+    pub jwt: JwtConfig,
+    // MQTT 5 enhanced authentication methods clients may request
+    pub methods: Vec<String>,
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Self {
+            allow_anonymous: true,
+            chain: vec![Source::File, Source::Jwt, Source::Http],
+            file: UsersConfig::default(),
+            jwt: JwtConfig::default(),
+            methods: vec![scram::METHOD.to_string()],
+        }
+    }
+}
+
+// Authenticator name in the chain
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    File,
+    Jwt,
+    Http,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::File => write!(f, "file"),
+            Self::Jwt => write!(f, "jwt"),
+            Self::Http => write!(f, "http"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("denied by {0} authenticator")]
+    Denied(Source),
+    #[error("anonymous access is not allowed")]
+    Anonymous,
+}
+
+// Identity of a verified TLS client certificate
+#[derive(Debug, Clone, Default)]
+pub struct PeerCert {
+    // Subject Common Name
+    pub cn: Option<String>,
+    // Subject Distinguished Name
+    pub dn: String,
+    // First DNS, email or URI Subject Alternative Name
+    pub san: Option<String>,
+}
+
+impl PeerCert {
+    pub fn parse(der: &[u8]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let subject = cert.subject();
+        let cn = subject.iter_common_name().next().and_then(|cn| cn.as_str().ok());
+        let san = cert.subject_alternative_name().ok().flatten().and_then(|san| {
+            san.value.general_names.iter().find_map(|name| match name {
+                GeneralName::DNSName(s) | GeneralName::RFC822Name(s) | GeneralName::URI(s) => {
+                    Some(s.to_string())
+                }
+                _ => None,
+            })
+        });
+        Some(Self { cn: cn.map(String::from), dn: subject.to_string(), san })
+    }
+
+    pub fn get(&self, field: CertField) -> Option<&str> {
+        match field {
+            CertField::Cn => self.cn.as_deref(),
+            CertField::Dn => Some(self.dn.as_str()),
+            CertField::San => self.san.as_deref(),
+        }
+    }
+}
+
+// Credentials of a unix socket peer, reported by the kernel
+#[derive(Debug, Clone, Copy)]
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+// Client identity presented on CONNECT
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub addr: SocketAddr,
+    // Protocol level, 3.1 / 3.1.1 / 5.0
+    pub proto_ver: String,
+    pub peer_cred: Option<PeerCred>,
+    // The listener accepts the client certificate or peer credentials as proof of identity
+    pub identified: bool,
+}
+
+impl ClientInfo {
+    // Replace ${clientid}, ${username}, ${password}, ${peerhost}, ${proto_ver}, ${uid} and ${gid}
+    pub fn fill(&self, template: &str) -> String {
+        let (uid, gid) = match self.peer_cred {
+            Some(cred) => (cred.uid.to_string(), cred.gid.to_string()),
+            None => Default::default(),
+        };
+        template
+            .replace("${clientid}", &self.client_id)
+            .replace("${username}", self.username.as_deref().unwrap_or_default())
+            .replace("${password}", self.password.as_deref().unwrap_or_default())
+            .replace("${peerhost}", &self.addr.ip().to_string())
+            .replace("${proto_ver}", &self.proto_ver)
+            .replace("${uid}", &uid)
+            .replace("${gid}", &gid)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum AclAction {
+    Publish,
+    Subscribe,
+    All,
+}
+
+// Per client topic rule returned by an authenticator, checked before the ACL rules
+#[derive(Debug, Deserialize, Clone)]
+pub struct TopicRule {
+    pub permission: Permission,
+    pub action: AclAction,
+    pub topic: String,
+}
+
+impl TopicRule {
+    // Permission of the first rule matching the action and topic, if any
+    pub fn check(rules: &[TopicRule], action: AclAction, topic: &str) -> Option<Permission> {
+        rules
+            .iter()
+            .find(|rule| {
+                (rule.action == action || rule.action == AclAction::All)
+                    && match action {
+                        AclAction::Subscribe => covers(&rule.topic, topic),
+                        _ => matches(&rule.topic, topic),
+                    }
+            })
+            .map(|rule| rule.permission)
+    }
+}
+
+// What an allowed client is granted
+#[derive(Debug, Clone, Default)]
+pub struct Grant {
+    pub is_superuser: bool,
+    pub acl: Vec<TopicRule>,
+}
+
+// Authentication result
+#[derive(Debug, Clone)]
+pub enum AuthResult {
+    Allow(Grant),
+    Deny,
+    // Not handled, left to the next authenticator
+    Ignore,
+}
+
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    async fn authenticate(&self, info: &ClientInfo) -> AuthResult;
+}
+
+// MQTT 5 enhanced authentication method, named by the Authentication Method property
+pub trait AuthMethod: Send + Sync {
+    fn start(&self) -> Box<dyn AuthExchange>;
+}
+
+// One exchange, fed the Authentication Data of the CONNECT or AUTH packets in turn
+#[async_trait]
+pub trait AuthExchange: Send + Sync {
+    async fn step(&mut self, data: &[u8]) -> Step;
+}
+
+// Outcome of an exchange step
+#[derive(Debug)]
+pub enum Step {
+    // Send the data in an AUTH Continue Authentication
+    Continue(Vec<u8>),
+    // Authenticated, the data goes into the CONNACK or AUTH Success
+    Success { data: Option<Vec<u8>>, username: Option<String>, grant: Grant },
+    Failure(String),
+}
+
+// Ordered authenticator chain, the first allow or deny wins
+pub struct AuthChain {
+    authenticators: HashMap<Source, Arc<dyn Authenticator>>,
+    methods: HashMap<String, Arc<dyn AuthMethod>>,
+}
+
+impl AuthChain {
+    pub fn new(plugins: Plugins, users: Arc<Users>, jwt: Option<Jwt>) -> Self {
+        let mut methods: HashMap<String, Arc<dyn AuthMethod>> = HashMap::new();
+        methods.insert(scram::METHOD.to_string(), Arc::new(Scram::new(users.clone())));
+
+        let mut authenticators: HashMap<Source, Arc<dyn Authenticator>> = HashMap::new();
+        authenticators.insert(Source::File, users);
+        if let Some(jwt) = jwt {
+            authenticators.insert(Source::Jwt, Arc::new(jwt));
+        }
+        if let Some(auth_http) = plugins.auth_http {
+            authenticators.insert(Source::Http, Arc::new(auth_http));
+        }
+        Self { authenticators, methods }
+    }
+
+    // Start an enhanced authentication exchange, None if the method is unknown or disabled
+    pub fn start(&self, cfg: &Auth, method: &str) -> Option<Box<dyn AuthExchange>> {
+        if !cfg.methods.iter().any(|m| m == method) {
+            return None;
+        }
+        self.methods.get(method).map(|method| method.start())
+    }
+
+    pub async fn authenticate(&self, cfg: &Auth, info: &ClientInfo) -> Result<Grant, AuthError> {
+        for source in &cfg.chain {
+            let Some(authenticator) = self.authenticators.get(source) else {
+                continue;
+            };
+            match authenticator.authenticate(info).await {
+                AuthResult::Allow(grant) => {
+                    debug!("{} allowed by {} authenticator", info.client_id, source);
+                    return Ok(grant);
+                }
+                AuthResult::Deny => return Err(AuthError::Denied(*source)),
+                AuthResult::Ignore => {}
+            }
+        }
+
+        if cfg.allow_anonymous || info.identified {
+            Ok(Grant::default())
+        } else {
+            Err(AuthError::Anonymous)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(fn() -> AuthResult);
+
+    #[async_trait]
+    impl Authenticator for Fixed {
+        async fn authenticate(&self, _info: &ClientInfo) -> AuthResult {
+            (self.0)()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain() {
+        let info = ClientInfo {
+            client_id: "c1".into(),
+            username: None,
+            password: None,
+            addr: "127.0.0.1:50000".parse().unwrap(),
+            proto_ver: "5.0".into(),
+            peer_cred: None,
+            identified: false,
+        };
+        let chain = |result: fn() -> AuthResult| {
+            let mut authenticators: HashMap<Source, Arc<dyn Authenticator>> = HashMap::new();
+            authenticators.insert(Source::Http, Arc::new(Fixed(result)));
+            AuthChain { authenticators, methods: HashMap::new() }
+        };
+        let cfg = |allow_anonymous| Auth {
+            allow_anonymous,
+            chain: vec![Source::Http],
+            ..Default::default()
+        };
+
+        let allow = chain(|| AuthResult::Allow(Grant { is_superuser: true, acl: Vec::new() }));
+        assert!(allow.authenticate(&cfg(false), &info).await.unwrap().is_superuser);
+
+        let deny = chain(|| AuthResult::Deny);
+        assert!(matches!(deny.authenticate(&cfg(true), &info).await, Err(AuthError::Denied(_))));
+
+        let ignore = chain(|| AuthResult::Ignore);
+        assert!(ignore.authenticate(&cfg(true), &info).await.is_ok());
+        assert!(matches!(ignore.authenticate(&cfg(false), &info).await, Err(AuthError::Anonymous)));
+        let identified = ClientInfo { identified: true, ..info.clone() };
+        assert!(ignore.authenticate(&cfg(false), &identified).await.is_ok());
+
+        // Authenticators not in the chain are skipped
+        let empty = Auth { allow_anonymous: false, chain: Vec::new(), ..Default::default() };
+        assert!(allow.authenticate(&empty, &info).await.is_err());
+    }
+
+    #[test]
+    fn test_peer_cert() {
+        use rcgen::{CertificateParams, DnType, KeyPair};
+
+        let mut params = CertificateParams::new(vec!["device-1.example.com".into()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "device-1");
+        params.distinguished_name.push(DnType::OrganizationName, "iotmq");
+        let cert = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+
+        let peer = PeerCert::parse(cert.der()).unwrap();
+        assert_eq!(peer.get(CertField::Cn), Some("device-1"));
+        assert_eq!(peer.get(CertField::San), Some("device-1.example.com"));
+        assert!(peer.dn.contains("CN=device-1"));
+        assert!(PeerCert::parse(b"not a certificate").is_none());
+    }
+
+    #[test]
+    fn test_topic_rule() {
+        let rule = |topic: &str| TopicRule {
+            permission: Permission::Allow,
+            action: AclAction::All,
+            topic: topic.into(),
+        };
+        let rules = [rule("devices/c1/+"), rule("+")];
+        let check = |action, topic| TopicRule::check(&rules, action, topic);
+
+        assert_eq!(check(AclAction::Publish, "devices/c1/up"), Some(Permission::Allow));
+        assert_eq!(check(AclAction::Subscribe, "devices/c1/+"), Some(Permission::Allow));
+        assert_eq!(check(AclAction::Subscribe, "devices/c1/#"), None);
+        assert_eq!(check(AclAction::Subscribe, "#"), None);
+    }
+}
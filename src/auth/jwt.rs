@@ -0,0 +1,261 @@
+use crate::auth::{AuthResult, Authenticator, ClientInfo, Grant, TopicRule};
+use async_trait::async_trait;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fs;
+use tracing::debug;
+
+// CONNECT field the token is read from
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenFrom {
+    Password,
+    Username,
+}
+
+// JWT authentication configuration
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct JwtConfig {
+    pub enable: bool,
+    pub from: TokenFrom,
+    // HS256 shared secret
+    pub secret: String,
+    // Whether the secret is base64 encoded
+    pub secret_base64: bool,
+    // PEM public key file for RS256 / ES256
+    pub public_key: String,
+    // Local JWKS file, keys are selected by the token kid
+    pub jwks: String,
+    // Seconds of clock skew allowed when checking exp and nbf
+    pub leeway: u64,
+    // Claim holding topic rules, [{"permission", "action", "topic"}]
+    pub acl_claim: String,
+    // Claim holding the superuser flag
+    pub superuser_claim: String,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            from: TokenFrom::Password,
+            secret: String::new(),
+            secret_base64: false,
+            public_key: String::new(),
+            jwks: String::new(),
+            leeway: 0,
+            acl_claim: "acl".to_string(),
+            superuser_claim: "is_superuser".to_string(),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum JwtError {
+    #[error("jwt key file {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("jwt key {0}: {1}")]
+    Key(String, jsonwebtoken::errors::Error),
+    #[error("jwks file {0}: {1}")]
+    Jwks(String, serde_json::Error),
+}
+
+// Verify JWTs presented in the CONNECT password or username
+pub struct Jwt {
+    cfg: JwtConfig,
+    secret: Option<DecodingKey>,
+    public_key: Option<(DecodingKey, Algorithm)>,
+    jwks: JwkSet,
+}
+
+impl Jwt {
+    pub fn new(cfg: JwtConfig) -> Result<Option<Self>, JwtError> {
+        if !cfg.enable {
+            return Ok(None);
+        }
+
+        let secret = match (cfg.secret.is_empty(), cfg.secret_base64) {
+            (true, _) => None,
+            (false, true) => Some(
+                DecodingKey::from_base64_secret(&cfg.secret)
+                    .map_err(|e| JwtError::Key("secret".into(), e))?,
+            ),
+            (false, false) => Some(DecodingKey::from_secret(cfg.secret.as_bytes())),
+        };
+
+        let public_key = if cfg.public_key.is_empty() {
+            None
+        } else {
+            let pem =
+                fs::read(&cfg.public_key).map_err(|e| JwtError::Io(cfg.public_key.clone(), e))?;
+            let key = match DecodingKey::from_rsa_pem(&pem) {
+                Ok(key) => (key, Algorithm::RS256),
+                Err(_) => DecodingKey::from_ec_pem(&pem)
+                    .map(|key| (key, Algorithm::ES256))
+                    .map_err(|e| JwtError::Key(cfg.public_key.clone(), e))?,
+            };
+            Some(key)
+        };
+
+        let jwks = if cfg.jwks.is_empty() {
+            JwkSet { keys: Vec::new() }
+        } else {
+            let data = fs::read(&cfg.jwks).map_err(|e| JwtError::Io(cfg.jwks.clone(), e))?;
+            serde_json::from_slice(&data).map_err(|e| JwtError::Jwks(cfg.jwks.clone(), e))?
+        };
+
+        Ok(Some(Self { cfg, secret, public_key, jwks }))
+    }
+
+    // Key for the token algorithm, a JWKS key with a matching kid is preferred
+    fn key(&self, alg: Algorithm, kid: Option<&str>) -> Option<DecodingKey> {
+        match alg {
+            Algorithm::HS256 => self.secret.clone(),
+            Algorithm::RS256 | Algorithm::ES256 => {
+                let jwk = match kid {
+                    Some(kid) => self.jwks.find(kid),
+                    None if self.jwks.keys.len() == 1 => self.jwks.keys.first(),
+                    None => None,
+                };
+                if let Some(key) = jwk.and_then(|jwk| DecodingKey::from_jwk(jwk).ok()) {
+                    return Some(key);
+                }
+                self.public_key.as_ref().filter(|(_, a)| *a == alg).map(|(key, _)| key.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn grant(&self, claims: &Map<String, Value>) -> Grant {
+        let is_superuser =
+            claims.get(&self.cfg.superuser_claim).and_then(Value::as_bool).unwrap_or_default();
+        let acl = claims
+            .get(&self.cfg.acl_claim)
+            .and_then(|acl| Vec::<TopicRule>::deserialize(acl).ok())
+            .unwrap_or_default();
+        Grant { is_superuser, acl }
+    }
+}
+
+#[async_trait]
+impl Authenticator for Jwt {
+    // Values that are not JWTs are left to the next authenticator
+    async fn authenticate(&self, info: &ClientInfo) -> AuthResult {
+        let token = match self.cfg.from {
+            TokenFrom::Password => info.password.as_deref(),
+            TokenFrom::Username => info.username.as_deref(),
+        };
+        let Some(header) = token.and_then(|token| decode_header(token).ok()) else {
+            return AuthResult::Ignore;
+        };
+        let Some(key) = self.key(header.alg, header.kid.as_deref()) else {
+            debug!("no jwt key for {:?} token of {}", header.alg, info.client_id);
+            return AuthResult::Deny;
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.cfg.leeway;
+        validation.validate_nbf = true;
+        match decode::<Map<String, Value>>(token.unwrap_or_default(), &key, &validation) {
+            Ok(data) => AuthResult::Allow(self.grant(&data.claims)),
+            Err(e) => {
+                debug!("jwt of {} rejected: {}", info.client_id, e);
+                AuthResult::Deny
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    fn info(password: &str) -> ClientInfo {
+        ClientInfo {
+            client_id: "c1".into(),
+            username: Some("u1".into()),
+            password: Some(password.into()),
+            addr: "127.0.0.1:50000".parse().unwrap(),
+            proto_ver: "5.0".into(),
+            peer_cred: None,
+            identified: false,
+        }
+    }
+
+    fn token(claims: Value, secret: &str) -> String {
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hs256() {
+        let cfg = JwtConfig { enable: true, secret: "s3cr3t".into(), ..Default::default() };
+        let jwt = Jwt::new(cfg).unwrap().unwrap();
+        let now = jsonwebtoken::get_current_timestamp();
+
+        let claims = json!({
+            "exp": now + 60,
+            "acl": [{ "permission": "allow", "action": "publish", "topic": "t/1" }],
+        });
+        match jwt.authenticate(&info(&token(claims, "s3cr3t"))).await {
+            AuthResult::Allow(grant) => {
+                assert!(!grant.is_superuser);
+                assert_eq!(grant.acl[0].topic, "t/1");
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+
+        let deny = |claims, secret| info(&token(claims, secret));
+        let expired = deny(json!({ "exp": now - 60 }), "s3cr3t");
+        let not_before = deny(json!({ "exp": now + 60, "nbf": now + 60 }), "s3cr3t");
+        let forged = deny(json!({ "exp": now + 60 }), "other");
+        for info in [expired, not_before, forged] {
+            assert!(matches!(jwt.authenticate(&info).await, AuthResult::Deny));
+        }
+        assert!(matches!(jwt.authenticate(&info("password")).await, AuthResult::Ignore));
+    }
+
+    #[tokio::test]
+    async fn test_es256_jwks() {
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD as B64;
+        use base64::Engine;
+        use ring::rand::SystemRandom;
+        use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let point = pair.public_key().as_ref();
+        let jwks = json!({ "keys": [{
+            "kty": "EC", "crv": "P-256", "kid": "k1",
+            "x": B64.encode(&point[1..33]), "y": B64.encode(&point[33..]),
+        }]});
+        let path = std::env::temp_dir().join(format!("iotmq-jwks-{}.json", std::process::id()));
+        fs::write(&path, jwks.to_string()).unwrap();
+
+        let cfg =
+            JwtConfig { enable: true, jwks: path.to_string_lossy().into(), ..Default::default() };
+        let jwt = Jwt::new(cfg).unwrap().unwrap();
+        fs::remove_file(path).unwrap();
+
+        let key = EncodingKey::from_ec_der(pkcs8.as_ref());
+        let claims =
+            json!({ "exp": jsonwebtoken::get_current_timestamp() + 60, "is_superuser": true });
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some("k1".into());
+        let token = encode(&header, &claims, &key).unwrap();
+        match jwt.authenticate(&info(&token)).await {
+            AuthResult::Allow(grant) => assert!(grant.is_superuser),
+            result => panic!("unexpected {:?}", result),
+        }
+
+        header.kid = Some("k2".into());
+        let token = encode(&header, &claims, &key).unwrap();
+        assert!(matches!(jwt.authenticate(&info(&token)).await, AuthResult::Deny));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::auth::users::{User, Users};
+use crate::auth::{AuthExchange, AuthMethod, Grant, Step};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac};
+use std::sync::Arc;
+
+pub const METHOD: &str = "SCRAM-SHA-256";
+
+// SCRAM-SHA-256 (RFC 5802, RFC 7677) against the built-in user database,
+// the stored PBKDF2 hash is the SCRAM SaltedPassword
+pub struct Scram {
+    users: Arc<Users>,
+    // Derives the salt of unknown users (RFC 5802 5.1), so they look like existing ones
+    secret: hmac::Key,
+}
+
+impl Scram {
+    pub fn new(users: Arc<Users>) -> Self {
+        Self { users, secret: hmac::Key::new(hmac::HMAC_SHA256, &random::<32>()) }
+    }
+}
+
+impl AuthMethod for Scram {
+    fn start(&self) -> Box<dyn AuthExchange> {
+        Box::new(Exchange {
+            users: self.users.clone(),
+            secret: self.secret.clone(),
+            state: State::ClientFirst,
+        })
+    }
+}
+
+enum State {
+    ClientFirst,
+    ClientFinal {
+        username: String,
+        // None for unknown users, the exchange goes on with a made up salt and fails at the end
+        user: Option<User>,
+        gs2_header: String,
+        nonce: String,
+        // client-first-message-bare "," server-first-message
+        auth_message: String,
+    },
+    Done,
+}
+
+struct Exchange {
+    users: Arc<Users>,
+    secret: hmac::Key,
+    state: State,
+}
+
+#[async_trait]
+impl AuthExchange for Exchange {
+    async fn step(&mut self, data: &[u8]) -> Step {
+        let Ok(message) = std::str::from_utf8(data) else {
+            return Step::Failure("scram message is not utf-8".into());
+        };
+        match std::mem::replace(&mut self.state, State::Done) {
+            State::ClientFirst => self.client_first(message),
+            State::ClientFinal { username, user, gs2_header, nonce, auth_message } => {
+                client_final(message, username, user, &gs2_header, &nonce, auth_message)
+            }
+            State::Done => Step::Failure("scram exchange already finished".into()),
+        }
+    }
+}
+
+impl Exchange {
+    // n,,n=<user>,r=<client nonce>
+    fn client_first(&mut self, message: &str) -> Step {
+        let (gs2_header, bare) = match message.split_once(",,") {
+            Some((cb @ ("n" | "y"), bare)) => (format!("{},,", cb), bare),
+            Some((cb, _)) if cb.starts_with("p=") => {
+                return Step::Failure("scram channel binding is not supported".into())
+            }
+            _ => return Step::Failure("invalid scram client-first-message".into()),
+        };
+        let (Some(username), Some(client_nonce)) = (attr(bare, 'n'), attr(bare, 'r')) else {
+            return Step::Failure("invalid scram client-first-message".into());
+        };
+        let username = username.replace("=2C", ",").replace("=3D", "=");
+
+        let user = self.users.get(&username);
+        let (salt, iterations) = match &user {
+            Some(user) => (user.salt.clone(), user.iterations),
+            None => {
+                let salt = hmac::sign(&self.secret, username.as_bytes());
+                (BASE64.encode(&salt.as_ref()[..16]), self.users.iterations())
+            }
+        };
+        let nonce = format!("{}{}", client_nonce, BASE64.encode(random::<18>()));
+        let server_first = format!("r={},s={},i={}", nonce, salt, iterations);
+        self.state = State::ClientFinal {
+            username,
+            user,
+            gs2_header,
+            nonce,
+            auth_message: format!("{},{}", bare, server_first),
+        };
+        Step::Continue(server_first.into_bytes())
+    }
+}
+
+// c=<base64 gs2 header>,r=<nonce>,p=<base64 proof>
+fn client_final(
+    message: &str,
+    username: String,
+    user: Option<User>,
+    gs2_header: &str,
+    nonce: &str,
+    auth_message: String,
+) -> Step {
+    let Some((without_proof, proof)) = message.rsplit_once(",p=") else {
+        return Step::Failure("invalid scram client-final-message".into());
+    };
+    if attr(without_proof, 'c') != Some(&BASE64.encode(gs2_header)) {
+        return Step::Failure("scram channel binding mismatch".into());
+    }
+    if attr(without_proof, 'r') != Some(nonce) {
+        return Step::Failure("scram nonce mismatch".into());
+    }
+    let (Some(user), Ok(proof)) = (user, BASE64.decode(proof)) else {
+        return Step::Failure(format!("scram authentication of {} failed", username));
+    };
+    let Ok(salted_password) = BASE64.decode(&user.hash) else {
+        return Step::Failure(format!("invalid password hash of {}", username));
+    };
+
+    let auth_message = format!("{},{}", auth_message, without_proof);
+    let keys = Keys::new(&salted_password);
+    let signature =
+        hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &keys.stored), auth_message.as_bytes());
+    let client_key: Vec<u8> = proof.iter().zip(signature.as_ref()).map(|(p, s)| p ^ s).collect();
+    let stored_key = digest::digest(&digest::SHA256, &client_key);
+    // Compared without an early exit
+    let diff = stored_key.as_ref().iter().zip(&keys.stored).fold(0, |acc, (a, b)| acc | (a ^ b));
+    if proof.len() != keys.stored.len() || diff != 0 {
+        return Step::Failure(format!("scram authentication of {} failed", username));
+    }
+
+    let server_key = hmac::Key::new(hmac::HMAC_SHA256, &keys.server);
+    let server_signature = hmac::sign(&server_key, auth_message.as_bytes());
+    Step::Success {
+        data: Some(format!("v={}", BASE64.encode(server_signature)).into_bytes()),
+        username: Some(username),
+        grant: Grant { is_superuser: user.is_superuser, acl: Vec::new() },
+    }
+}
+
+// StoredKey and ServerKey derived from the SaltedPassword
+struct Keys {
+    stored: Vec<u8>,
+    server: Vec<u8>,
+}
+
+impl Keys {
+    fn new(salted_password: &[u8]) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, salted_password);
+        let client_key = hmac::sign(&key, b"Client Key");
+        let server_key = hmac::sign(&key, b"Server Key");
+        Self {
+            stored: digest::digest(&digest::SHA256, client_key.as_ref()).as_ref().to_vec(),
+            server: server_key.as_ref().to_vec(),
+        }
+    }
+}
+
+// Value of a single letter attribute, "a=value"
+fn attr(message: &str, name: char) -> Option<&str> {
+    message.split(',').find_map(|kv| kv.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut buf = [0u8; N];
+    SystemRandom::new().fill(&mut buf).expect("system random source");
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::users::UsersConfig;
+    use ring::pbkdf2;
+    use std::num::NonZeroU32;
+
+    // Client side of the exchange
+    fn client_proof(
+        password: &str,
+        server_first: &str,
+        client_first_bare: &str,
+    ) -> (String, Vec<u8>) {
+        let salt = BASE64.decode(attr(server_first, 's').unwrap()).unwrap();
+        let iterations: u32 = attr(server_first, 'i').unwrap().parse().unwrap();
+        let mut salted_password = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(iterations).unwrap(),
+            &salt,
+            password.as_bytes(),
+            &mut salted_password,
+        );
+
+        let without_proof = format!("c=biws,r={}", attr(server_first, 'r').unwrap());
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &salted_password);
+        let client_key = hmac::sign(&key, b"Client Key");
+        let stored_key = digest::digest(&digest::SHA256, client_key.as_ref());
+        let signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, stored_key.as_ref()),
+            auth_message.as_bytes(),
+        );
+        let proof: Vec<u8> =
+            client_key.as_ref().iter().zip(signature.as_ref()).map(|(k, s)| k ^ s).collect();
+
+        let server_key = hmac::sign(&key, b"Server Key");
+        let server_signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, server_key.as_ref()),
+            auth_message.as_bytes(),
+        );
+        let verifier = format!("v={}", BASE64.encode(server_signature)).into_bytes();
+        (format!("{},p={}", without_proof, BASE64.encode(proof)), verifier)
+    }
+
+    // Run a full exchange, returns the step of the client-final-message and the expected verifier
+    async fn exchange(scram: &Scram, username: &str, password: &str) -> (Step, Vec<u8>) {
+        let mut exchange = scram.start();
+        let bare = format!("n={},r=fyko+d2lbbFgONRv9qkxdawL", username);
+        let server_first = match exchange.step(format!("n,,{}", bare).as_bytes()).await {
+            Step::Continue(data) => String::from_utf8(data).unwrap(),
+            step => panic!("unexpected {:?}", step),
+        };
+        assert!(server_first.starts_with("r=fyko+d2lbbFgONRv9qkxdawL"));
+
+        let (client_final, verifier) = client_proof(password, &server_first, &bare);
+        (exchange.step(client_final.as_bytes()).await, verifier)
+    }
+
+    #[tokio::test]
+    async fn test_scram() {
+        let path = std::env::temp_dir().join(format!("iotmq-scram-{}.json", std::process::id()));
+        let cfg = UsersConfig { path: path.to_string_lossy().into(), iterations: 64 };
+        let users = Arc::new(Users::load(cfg).unwrap());
+        users.add("alice", "pencil", true).unwrap();
+        let scram = Scram::new(users);
+
+        match exchange(&scram, "alice", "pencil").await {
+            (Step::Success { data, username, grant }, verifier) => {
+                assert_eq!(data, Some(verifier));
+                assert_eq!(username.as_deref(), Some("alice"));
+                assert!(grant.is_superuser);
+            }
+            (step, _) => panic!("unexpected {:?}", step),
+        }
+        assert!(matches!(exchange(&scram, "alice", "pen").await.0, Step::Failure(_)));
+        // Unknown users get a challenge like everyone else
+        assert!(matches!(exchange(&scram, "bob", "pencil").await.0, Step::Failure(_)));
+
+        // Repeated attempts see the same salt and iterations whether the user exists or not
+        let server_first = |username: &'static str| {
+            let mut exchange = scram.start();
+            async move {
+                let first = format!("n,,n={},r=abc", username);
+                let Step::Continue(data) = exchange.step(first.as_bytes()).await else {
+                    panic!("no server-first-message");
+                };
+                let data = String::from_utf8(data).unwrap();
+                (attr(&data, 's').unwrap().to_string(), attr(&data, 'i').unwrap().to_string())
+            }
+        };
+        for username in ["alice", "bob"] {
+            assert_eq!(server_first(username).await, server_first(username).await);
+        }
+        assert_ne!(server_first("bob").await, server_first("carol").await);
+
+        let mut exchange = scram.start();
+        assert!(matches!(exchange.step(b"p=tls-unique,,n=alice,r=x").await, Step::Failure(_)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+use crate::auth::{AuthResult, Authenticator, ClientInfo, Grant};
+use crate::Context;
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use tokio::task::spawn_blocking;
+use tokio::time::{interval, Duration};
+use tracing::warn;
+
+const ALGORITHM: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+// How often a running server checks the file for changes made by the cli
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Built-in user database configuration
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct UsersConfig {
+    // JSON file the users are stored in
+    pub path: String,
+    // PBKDF2 iterations used for new passwords, every CONNECT with a password pays for
+    // them on a blocking thread and SCRAM clients pay for them too
+    pub iterations: u32,
+}
+
+impl Default for UsersConfig {
+    fn default() -> Self {
+        Self { path: "./data/users.json".to_string(), iterations: 600_000 }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum UsersError {
+    #[error("users file {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("users file {0}: {1}")]
+    Parse(String, serde_json::Error),
+    #[error("username must not be empty")]
+    EmptyUsername,
+}
+
+// Stored user, the password is kept as a salted PBKDF2-HMAC-SHA256 hash
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub salt: String,
+    pub hash: String,
+    pub iterations: u32,
+    #[serde(default)]
+    pub is_superuser: bool,
+}
+
+impl User {
+    fn new(password: &str, iterations: u32, is_superuser: bool) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        SystemRandom::new().fill(&mut salt).expect("system random source");
+        let iterations = iterations.max(1);
+        let mut hash = [0u8; HASH_LEN];
+        pbkdf2::derive(
+            ALGORITHM,
+            NonZeroU32::new(iterations).unwrap(),
+            &salt,
+            password.as_bytes(),
+            &mut hash,
+        );
+        Self { salt: BASE64.encode(salt), hash: BASE64.encode(hash), iterations, is_superuser }
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        let (Ok(salt), Ok(hash), Some(iterations)) = (
+            BASE64.decode(&self.salt),
+            BASE64.decode(&self.hash),
+            NonZeroU32::new(self.iterations),
+        ) else {
+            return false;
+        };
+        pbkdf2::verify(ALGORITHM, iterations, &salt, password.as_bytes(), &hash).is_ok()
+    }
+}
+
+// User as listed by the api and cli
+#[derive(Debug, Serialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub is_superuser: bool,
+}
+
+struct Inner {
+    users: BTreeMap<String, User>,
+    // Modification time of the file when it was read
+    modified: Option<SystemTime>,
+}
+
+// Users stored in a local file
+pub struct Users {
+    cfg: UsersConfig,
+    inner: RwLock<Inner>,
+}
+
+impl Users {
+    pub fn load(cfg: UsersConfig) -> Result<Self, UsersError> {
+        let (users, modified) = Self::read(&cfg.path)?;
+        Ok(Self { cfg, inner: RwLock::new(Inner { users, modified }) })
+    }
+
+    // Add a user or replace the password of an existing one
+    pub fn add(
+        &self,
+        username: &str,
+        password: &str,
+        is_superuser: bool,
+    ) -> Result<(), UsersError> {
+        if username.is_empty() {
+            return Err(UsersError::EmptyUsername);
+        }
+        self.refresh();
+        let user = User::new(password, self.cfg.iterations, is_superuser);
+        let mut inner = self.inner.write().unwrap();
+        inner.users.insert(username.to_string(), user);
+        self.save(&mut inner)
+    }
+
+    // Delete a user, returns whether it existed
+    pub fn delete(&self, username: &str) -> Result<bool, UsersError> {
+        self.refresh();
+        let mut inner = self.inner.write().unwrap();
+        if inner.users.remove(username).is_none() {
+            return Ok(false);
+        }
+        self.save(&mut inner).map(|_| true)
+    }
+
+    pub fn get(&self, username: &str) -> Option<User> {
+        self.inner.read().unwrap().users.get(username).cloned()
+    }
+
+    // PBKDF2 iterations used for new passwords
+    pub fn iterations(&self) -> u32 {
+        self.cfg.iterations.max(1)
+    }
+
+    pub fn list(&self) -> Vec<UserInfo> {
+        self.refresh();
+        let inner = self.inner.read().unwrap();
+        inner
+            .users
+            .iter()
+            .map(|(username, user)| UserInfo {
+                username: username.clone(),
+                is_superuser: user.is_superuser,
+            })
+            .collect()
+    }
+
+    fn read(path: &str) -> Result<(BTreeMap<String, User>, Option<SystemTime>), UsersError> {
+        if !Path::new(path).exists() {
+            return Ok((BTreeMap::new(), None));
+        }
+        let data = fs::read(path).map_err(|e| UsersError::Io(path.to_string(), e))?;
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let users = if data.is_empty() {
+            BTreeMap::new()
+        } else {
+            serde_json::from_slice(&data).map_err(|e| UsersError::Parse(path.to_string(), e))?
+        };
+        Ok((users, modified))
+    }
+
+    // Reread the file if it changed since it was last read
+    fn refresh(&self) {
+        let modified = fs::metadata(&self.cfg.path).and_then(|m| m.modified()).ok();
+        if self.inner.read().unwrap().modified == modified {
+            return;
+        }
+        match Self::read(&self.cfg.path) {
+            Ok((users, modified)) => *self.inner.write().unwrap() = Inner { users, modified },
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    // Write through a temporary file readable by the owner only
+    fn save(&self, inner: &mut Inner) -> Result<(), UsersError> {
+        let path = Path::new(&self.cfg.path);
+        let io_err = |e| UsersError::Io(self.cfg.path.clone(), e);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(io_err)?;
+        }
+
+        let tmp = path.with_extension("tmp");
+        let data = serde_json::to_vec_pretty(&inner.users)
+            .map_err(|e| UsersError::Parse(self.cfg.path.clone(), e))?;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .map_err(io_err)?;
+        file.write_all(&data).and_then(|_| file.sync_all()).map_err(io_err)?;
+        fs::rename(&tmp, path).map_err(io_err)?;
+
+        inner.modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Authenticator for Users {
+    // Unknown users are left to the next authenticator
+    async fn authenticate(&self, info: &ClientInfo) -> AuthResult {
+        let (Some(username), Some(password)) = (&info.username, &info.password) else {
+            return AuthResult::Ignore;
+        };
+        let Some(user) = self.get(username) else {
+            return AuthResult::Ignore;
+        };
+        // PBKDF2 is slow on purpose, keep it off the runtime threads
+        let is_superuser = user.is_superuser;
+        let password = password.clone();
+        match spawn_blocking(move || user.verify(&password)).await {
+            Ok(true) => AuthResult::Allow(Grant { is_superuser, acl: Vec::new() }),
+            _ => AuthResult::Deny,
+        }
+    }
+}
+
+// Pick up changes made to the file by the cli while the server runs
+pub async fn run(ctx: Context) {
+    let mut shutdown = ctx.subscribe();
+    let mut ticker = interval(REFRESH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                // The file is stat'ed and read with std::fs
+                let ctx = ctx.clone();
+                let _ = spawn_blocking(move || ctx.users().refresh()).await;
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users() {
+        let path = std::env::temp_dir().join(format!("iotmq-users-{}.json", std::process::id()));
+        let cfg = UsersConfig { path: path.to_string_lossy().into(), iterations: 16 };
+
+        let users = Users::load(cfg.clone()).unwrap();
+        users.add("alice", "secret", true).unwrap();
+        users.add("bob", "hunter2", false).unwrap();
+        assert!(matches!(users.add("", "x", false), Err(UsersError::EmptyUsername)));
+
+        // Persisted and salted
+        let loaded = Users::load(cfg).unwrap();
+        let inner = loaded.inner.read().unwrap();
+        let alice = &inner.users["alice"];
+        assert!(alice.verify("secret") && !alice.verify("Secret"));
+        assert_ne!(alice.salt, inner.users["bob"].salt);
+        assert!(!fs::read_to_string(&path).unwrap().contains("secret"));
+        drop(inner);
+
+        assert_eq!(loaded.list().len(), 2);
+        assert!(loaded.delete("bob").unwrap());
+        assert!(!loaded.delete("bob").unwrap());
+        assert_eq!(users.list().len(), 1);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+use crate::broker::valid_level;
+use crate::protocol::{QoS, SubscribeOptions};
+use serde::{Deserialize, Deserializer};
+
+// Subscription installed for every new session
+#[derive(Debug, Deserialize, Clone)]
+pub struct AutoSubscribe {
+    // Topic filter template, supports ${clientid} and ${username}
+    pub topic: String,
+    #[serde(default, deserialize_with = "AutoSubscribe::deserialize_qos")]
+    pub qos: QoS,
+    // No Local
+    #[serde(default)]
+    pub nl: bool,
+    // Retain As Published
+    #[serde(default)]
+    pub rap: bool,
+    // Retain Handling, 0 to 2
+    #[serde(default, deserialize_with = "AutoSubscribe::deserialize_rh")]
+    pub rh: u8,
+}
+
+impl AutoSubscribe {
+    // Deserialize for qos
+    fn deserialize_qos<'de, D: Deserializer<'de>>(deserializer: D) -> Result<QoS, D::Error> {
+        let qos = u8::deserialize(deserializer)?;
+        QoS::try_from(qos).map_err(serde::de::Error::custom)
+    }
+
+    // Deserialize for rh
+    fn deserialize_rh<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+        let rh = u8::deserialize(deserializer)?;
+        if rh > 2 {
+            return Err(serde::de::Error::custom(format!("invalid retain handling {}", rh)));
+        }
+        Ok(rh)
+    }
+
+    // Topic filter for the client, None if a placeholder has no value or one that is not
+    // a plain topic level
+    pub fn filter(&self, client_id: &str, username: Option<&str>) -> Option<String> {
+        let mut filter = self.topic.clone();
+        if filter.contains("${clientid}") {
+            filter = filter.replace("${clientid}", Some(client_id).filter(|c| valid_level(c))?);
+        }
+        if filter.contains("${username}") {
+            filter = filter.replace("${username}", username.filter(|u| valid_level(u))?);
+        }
+        Some(filter)
+    }
+
+    pub fn options(&self) -> SubscribeOptions {
+        SubscribeOptions {
+            qos: self.qos,
+            no_local: self.nl,
+            retain_as_published: self.rap,
+            retain_handling: self.rh,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn auto(topic: &str) -> AutoSubscribe {
+        serde_json::from_value(json!({ "topic": topic })).unwrap()
+    }
+
+    #[test]
+    fn test_filter() {
+        let devices = auto("devices/${clientid}/users/${username}");
+        assert_eq!(devices.filter("c1", Some("u1")).as_deref(), Some("devices/c1/users/u1"));
+        assert_eq!(devices.filter("c1", None), None);
+        // Wildcards and separators would subscribe to other clients' topics
+        assert_eq!(devices.filter("#", Some("u1")), None);
+        assert_eq!(devices.filter("c1", Some("+")), None);
+        assert_eq!(devices.filter("c1/x", Some("u1")), None);
+
+        assert_eq!(auto("news/#").filter("#", None).as_deref(), Some("news/#"));
+    }
+
+    #[test]
+    fn test_options() {
+        let auto: AutoSubscribe =
+            serde_json::from_value(json!({ "topic": "a", "qos": 1, "nl": true, "rh": 2 })).unwrap();
+        let options = auto.options();
+        assert_eq!(options.qos, QoS::AtLeastOnce);
+        assert!(options.no_local);
+        assert_eq!(options.retain_handling, 2);
+
+        assert!(serde_json::from_value::<AutoSubscribe>(json!({ "topic": "a", "rh": 3 })).is_err());
+        assert!(serde_json::from_value::<AutoSubscribe>(json!({ "topic": "a", "qos": 3 })).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::acl::Cidr;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::RwLock;
+
+// Ban list configuration
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct BannedConfig {
+    // JSON file the ban list is stored in
+    pub path: String,
+}
+
+impl Default for BannedConfig {
+    fn default() -> Self {
+        Self { path: "./data/banned.json".to_string() }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BannedError {
+    #[error("ban list file {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("ban list file {0}: {1}")]
+    Parse(String, serde_json::Error),
+    #[error("{0}")]
+    InvalidPeerhost(String),
+    #[error("ban target must not be empty")]
+    Empty,
+    #[error("ban expiry {0} is in the past")]
+    Expired(DateTime<Local>),
+}
+
+// What a ban matches
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum BanAs {
+    ClientId,
+    Username,
+    // IP address or CIDR range, checked when the connection is accepted
+    Peerhost,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Ban {
+    #[serde(rename = "as")]
+    pub kind: BanAs,
+    pub who: String,
+    #[serde(default)]
+    pub reason: String,
+    pub at: DateTime<Local>,
+    // None bans until deleted
+    #[serde(default)]
+    pub until: Option<DateTime<Local>>,
+}
+
+impl Ban {
+    fn active(&self, now: DateTime<Local>) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+}
+
+struct Inner {
+    bans: BTreeMap<(BanAs, String), Ban>,
+    // Parsed peerhost bans, checked for every accepted connection
+    networks: Vec<(Cidr, Option<DateTime<Local>>)>,
+}
+
+impl Inner {
+    fn new(bans: BTreeMap<(BanAs, String), Ban>) -> Result<Self, BannedError> {
+        let mut networks = Vec::new();
+        for ban in bans.values().filter(|ban| ban.kind == BanAs::Peerhost) {
+            let cidr = ban.who.parse().map_err(BannedError::InvalidPeerhost)?;
+            networks.push((cidr, ban.until));
+        }
+        Ok(Self { bans, networks })
+    }
+}
+
+// Banned client ids, usernames and peer addresses stored in a local file
+pub struct Banned {
+    cfg: BannedConfig,
+    inner: RwLock<Inner>,
+}
+
+impl Banned {
+    pub fn load(cfg: BannedConfig) -> Result<Self, BannedError> {
+        let path = &cfg.path;
+        let bans: Vec<Ban> = match fs::read(path) {
+            Ok(data) if !data.is_empty() => serde_json::from_slice(&data)
+                .map_err(|e| BannedError::Parse(path.to_string(), e))?,
+            Ok(_) => Vec::new(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(BannedError::Io(path.to_string(), e)),
+        };
+        let bans = bans.into_iter().map(|ban| ((ban.kind, ban.who.clone()), ban)).collect();
+        Ok(Self { inner: RwLock::new(Inner::new(bans)?), cfg })
+    }
+
+    // Add a ban or replace an existing one for the same target
+    pub fn add(&self, ban: Ban) -> Result<(), BannedError> {
+        if ban.who.is_empty() {
+            return Err(BannedError::Empty);
+        }
+        if let Some(until) = ban.until.filter(|until| *until <= Local::now()) {
+            return Err(BannedError::Expired(until));
+        }
+        if ban.kind == BanAs::Peerhost {
+            ban.who.parse::<Cidr>().map_err(BannedError::InvalidPeerhost)?;
+        }
+
+        let mut inner = self.inner.write().unwrap();
+        let mut bans = inner.bans.clone();
+        bans.insert((ban.kind, ban.who.clone()), ban);
+        self.save(&mut inner, bans)
+    }
+
+    // Delete a ban, returns whether it existed
+    pub fn delete(&self, kind: BanAs, who: &str) -> Result<bool, BannedError> {
+        let mut inner = self.inner.write().unwrap();
+        let mut bans = inner.bans.clone();
+        if bans.remove(&(kind, who.to_string())).is_none() {
+            return Ok(false);
+        }
+        self.save(&mut inner, bans).map(|_| true)
+    }
+
+    // Bans in effect
+    pub fn list(&self) -> Vec<Ban> {
+        let now = Local::now();
+        let inner = self.inner.read().unwrap();
+        inner.bans.values().filter(|ban| ban.active(now)).cloned().collect()
+    }
+
+    pub fn peerhost(&self, ip: IpAddr) -> bool {
+        let now = Local::now();
+        let inner = self.inner.read().unwrap();
+        inner
+            .networks
+            .iter()
+            .any(|(cidr, until)| cidr.contains(ip) && until.is_none_or(|until| until > now))
+    }
+
+    // Ban in effect for the client id or username
+    pub fn identity(&self, client_id: &str, username: Option<&str>) -> Option<Ban> {
+        let now = Local::now();
+        let inner = self.inner.read().unwrap();
+        let get = |kind, who: &str| inner.bans.get(&(kind, who.to_string()));
+        get(BanAs::ClientId, client_id)
+            .into_iter()
+            .chain(username.and_then(|username| get(BanAs::Username, username)))
+            .find(|ban| ban.active(now))
+            .cloned()
+    }
+
+    // Write the bans in effect through a temporary file
+    fn save(
+        &self,
+        inner: &mut Inner,
+        mut bans: BTreeMap<(BanAs, String), Ban>,
+    ) -> Result<(), BannedError> {
+        let now = Local::now();
+        bans.retain(|_, ban| ban.active(now));
+
+        let path = Path::new(&self.cfg.path);
+        let io_err = |e| BannedError::Io(self.cfg.path.clone(), e);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(io_err)?;
+        }
+        let list: Vec<&Ban> = bans.values().collect();
+        let data = serde_json::to_vec_pretty(&list)
+            .map_err(|e| BannedError::Parse(self.cfg.path.clone(), e))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data).and_then(|_| fs::rename(&tmp, path)).map_err(io_err)?;
+
+        *inner = Inner::new(bans)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn ban(kind: BanAs, who: &str, until: Option<DateTime<Local>>) -> Ban {
+        Ban { kind, who: who.into(), reason: "test".into(), at: Local::now(), until }
+    }
+
+    #[test]
+    fn test_banned() {
+        let path = std::env::temp_dir().join(format!("iotmq-banned-{}.json", std::process::id()));
+        let cfg = BannedConfig { path: path.to_string_lossy().into() };
+        let banned = Banned::load(cfg.clone()).unwrap();
+
+        let later = Local::now() + TimeDelta::hours(1);
+        banned.add(ban(BanAs::Peerhost, "10.1.0.0/16", None)).unwrap();
+        banned.add(ban(BanAs::ClientId, "c1", Some(later))).unwrap();
+        banned.add(ban(BanAs::Username, "u1", None)).unwrap();
+        assert!(banned.add(ban(BanAs::Peerhost, "10.1.0.0/40", None)).is_err());
+        let earlier = Local::now() - TimeDelta::hours(1);
+        assert!(matches!(
+            banned.add(ban(BanAs::ClientId, "c2", Some(earlier))),
+            Err(BannedError::Expired(_))
+        ));
+
+        // Persisted
+        let banned = Banned::load(cfg).unwrap();
+        assert_eq!(banned.list().len(), 3);
+        assert!(banned.peerhost("10.1.2.3".parse().unwrap()));
+        assert!(!banned.peerhost("10.2.0.1".parse().unwrap()));
+        assert!(banned.identity("c1", None).is_some());
+        assert!(banned.identity("c3", Some("u1")).is_some());
+        assert!(banned.identity("c3", Some("u2")).is_none());
+
+        assert!(banned.delete(BanAs::Peerhost, "10.1.0.0/16").unwrap());
+        assert!(!banned.delete(BanAs::Peerhost, "10.1.0.0/16").unwrap());
+        assert!(!banned.peerhost("10.1.2.3".parse().unwrap()));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+use crate::metrics::Metrics;
+use crate::protocol::v5::PublishProperties;
+use crate::protocol::{Publish, QoS, SubscribeOptions};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, RwLock};
+
+// Message routed between sessions
+#[derive(Debug, Clone, Default)]
+pub struct Message {
+    // Publisher client id, empty for messages published by the broker itself
+    pub from: String,
+    pub topic: String,
+    pub qos: QoS,
+    pub retain: bool,
+    pub payload: Bytes,
+    pub properties: Option<PublishProperties>,
+}
+
+impl Message {
+    // Message published by the broker
+    pub fn new<T: Into<String>, P: Into<Bytes>>(topic: T, payload: P) -> Self {
+        Self { topic: topic.into(), payload: payload.into(), ..Default::default() }
+    }
+
+    pub fn from_publish(from: &str, publish: Publish) -> Self {
+        // Topic alias and subscription identifiers are per connection
+        let properties = publish.properties.map(|mut prop| {
+            prop.topic_alias = None;
+            prop.sub_identifiers.clear();
+            prop
+        });
+        Self {
+            from: from.to_string(),
+            topic: publish.topic,
+            qos: publish.qos,
+            retain: publish.retain,
+            payload: publish.payload,
+            properties,
+        }
+    }
+}
+
+// Message delivered to a session with the options of the matched subscription
+pub type Delivery = (Message, SubscribeOptions);
+
+#[derive(Default)]
+struct Inner {
+    // Client id -> (connection id, sender)
+    sessions: HashMap<String, (u64, mpsc::Sender<Delivery>)>,
+    // Topic filter -> client id -> options
+    subscriptions: HashMap<String, HashMap<String, SubscribeOptions>>,
+    retained: HashMap<String, Message>,
+}
+
+// Message router shared by all sessions
+pub struct Broker {
+    inner: RwLock<Inner>,
+    conn_id: AtomicU64,
+    pub metrics: Metrics,
+}
+
+impl Broker {
+    pub fn new() -> Self {
+        Self {
+            inner: RwLock::new(Inner::default()),
+            conn_id: AtomicU64::new(0),
+            metrics: Metrics::new(),
+        }
+    }
+
+    // Register a session and return its connection id. An existing session with the same
+    // client id is taken over, dropping its sender closes the old session's receiver
+    pub async fn connect(&self, client_id: &str, tx: mpsc::Sender<Delivery>) -> u64 {
+        let conn_id = self.conn_id.fetch_add(1, Ordering::Relaxed);
+        let mut inner = self.inner.write().await;
+        if inner.sessions.insert(client_id.to_string(), (conn_id, tx)).is_some() {
+            remove_subscriptions(&mut inner, client_id);
+        }
+        Metrics::inc(&self.metrics.clients_connected, 1);
+        conn_id
+    }
+
+    // Unregister a session unless it has already been taken over
+    pub async fn disconnect(&self, client_id: &str, conn_id: u64) {
+        let mut inner = self.inner.write().await;
+        if inner.sessions.get(client_id).is_some_and(|(id, _)| *id == conn_id) {
+            inner.sessions.remove(client_id);
+            remove_subscriptions(&mut inner, client_id);
+        }
+        Metrics::inc(&self.metrics.clients_disconnected, 1);
+    }
+
+    // Add a subscription, returns whether it already existed
+    pub async fn subscribe(
+        &self,
+        client_id: &str,
+        filter: &str,
+        options: SubscribeOptions,
+    ) -> bool {
+        let mut inner = self.inner.write().await;
+        let clients = inner.subscriptions.entry(filter.to_string()).or_default();
+        clients.insert(client_id.to_string(), options).is_some()
+    }
+
+    // Remove a subscription, returns whether it existed
+    pub async fn unsubscribe(&self, client_id: &str, filter: &str) -> bool {
+        let mut inner = self.inner.write().await;
+        let Some(clients) = inner.subscriptions.get_mut(filter) else {
+            return false;
+        };
+        let existed = clients.remove(client_id).is_some();
+        if clients.is_empty() {
+            inner.subscriptions.remove(filter);
+        }
+        existed
+    }
+
+    // Route a message to the matching subscribers, returns the number of receivers
+    pub async fn publish(&self, msg: Message) -> usize {
+        let inner = if msg.retain {
+            let mut inner = self.inner.write().await;
+            if msg.payload.is_empty() {
+                inner.retained.remove(&msg.topic);
+            } else {
+                inner.retained.insert(msg.topic.clone(), msg.clone());
+            }
+            inner.downgrade()
+        } else {
+            self.inner.read().await
+        };
+
+        // A client with overlapping subscriptions receives the message once, at the highest QoS
+        let mut receivers: HashMap<&str, SubscribeOptions> = HashMap::new();
+        for (filter, clients) in inner.subscriptions.iter() {
+            if !matches(filter, &msg.topic) {
+                continue;
+            }
+            for (client_id, options) in clients {
+                let entry = receivers.entry(client_id.as_str()).or_insert(*options);
+                if options.qos > entry.qos {
+                    *entry = *options;
+                }
+            }
+        }
+
+        // Client messages without subscribers are dropped, broker messages are not counted
+        if receivers.is_empty() {
+            if !msg.from.is_empty() {
+                Metrics::inc(&self.metrics.messages_dropped, 1);
+            }
+            return 0;
+        }
+
+        let mut count = 0;
+        for (client_id, options) in receivers {
+            let Some((_, tx)) = inner.sessions.get(client_id) else {
+                continue;
+            };
+            match tx.try_send((msg.clone(), options)) {
+                Ok(()) => count += 1,
+                Err(TrySendError::Full(_) | TrySendError::Closed(_)) => {
+                    Metrics::inc(&self.metrics.messages_dropped, 1);
+                }
+            }
+        }
+        count
+    }
+
+    // Retained messages matching the topic filter
+    pub async fn retained(&self, filter: &str) -> Vec<Message> {
+        let inner = self.inner.read().await;
+        inner.retained.values().filter(|msg| matches(filter, &msg.topic)).cloned().collect()
+    }
+
+    pub async fn session_count(&self) -> usize {
+        self.inner.read().await.sessions.len()
+    }
+
+    pub async fn subscription_count(&self) -> usize {
+        self.inner.read().await.subscriptions.values().map(|clients| clients.len()).sum()
+    }
+
+    pub async fn retained_count(&self) -> usize {
+        self.inner.read().await.retained.len()
+    }
+}
+
+fn remove_subscriptions(inner: &mut Inner, client_id: &str) {
+    inner.subscriptions.retain(|_, clients| {
+        clients.remove(client_id);
+        !clients.is_empty()
+    });
+}
+
+// Check whether the topic name matches the topic filter
+pub fn matches(filter: &str, topic: &str) -> bool {
+    // Topics beginning with $ are not matched by filters starting with a wildcard
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut filters = filter.split('/');
+    let mut topics = topic.split('/');
+    loop {
+        match (filters.next(), topics.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+// Whether every topic the subscription filter matches is also matched by the rule filter,
+// wildcards in the filter are only covered by the same or a wider wildcard in the rule
+pub fn covers(rule: &str, filter: &str) -> bool {
+    if filter.starts_with('$') && (rule.starts_with('+') || rule.starts_with('#')) {
+        return false;
+    }
+
+    let mut rules = rule.split('/');
+    let mut filters = filter.split('/');
+    loop {
+        match (rules.next(), filters.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(f)) if f != "#" => {}
+            (Some(r), Some(f)) if r == f && r != "+" => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+// Whether a client id or username can be substituted as a topic level, wildcards or
+// separators in it would widen the filter to other clients' topics
+pub fn valid_level(value: &str) -> bool {
+    !value.contains(['+', '#', '/'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("a/b/c", "a/b/c"));
+        assert!(matches("a/+/c", "a/b/c"));
+        assert!(matches("a/#", "a/b/c"));
+        assert!(matches("a/#", "a"));
+        assert!(matches("#", "a/b"));
+        assert!(matches("+/+", "/a"));
+        assert!(!matches("a/+", "a/b/c"));
+        assert!(!matches("a/b", "a/b/c"));
+        assert!(!matches("a/b/c", "a/b"));
+        assert!(!matches("#", "$SYS/brokers"));
+        assert!(!matches("+/brokers", "$SYS/brokers"));
+        assert!(matches("$SYS/#", "$SYS/brokers"));
+    }
+
+    #[test]
+    fn test_covers() {
+        assert!(covers("a/b", "a/b"));
+        assert!(covers("a/+", "a/b"));
+        assert!(covers("a/+", "a/+"));
+        assert!(covers("a/#", "a/+/c"));
+        assert!(covers("a/#", "a/#"));
+        assert!(covers("+", "+"));
+        assert!(covers("#", "#"));
+        assert!(covers("$SYS/#", "$SYS/+"));
+        // A wildcard subscription reaches topics a narrower rule does not grant
+        assert!(!covers("a/+", "a/#"));
+        assert!(!covers("+", "#"));
+        assert!(!covers("a/b", "a/+"));
+        assert!(!covers("a/+/c", "a/#"));
+        assert!(!covers("a/+", "a/b/c"));
+        assert!(!covers("#", "$SYS/#"));
+    }
+}
@@ -1,5 +1,9 @@
-use crate::Server;
+use crate::auth::users::Users;
+use crate::{Server, CFG};
 use clap::{Parser, Subcommand};
+use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+use std::io::{self, IsTerminal};
+use std::process::exit;
 
 #[derive(Parser)]
 #[command(version, about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -18,10 +22,29 @@ enum SubCmd {
     Stop,
     /// Restart IotMQ
     Restart,
-    /// Reload configuration file
+    /// Reload TLS certificates of the running server
     Reload,
     /// Show IotMQ Status
     Status,
+    /// Manage built-in users
+    User {
+        #[command(subcommand)]
+        command: UserCmd,
+    },
+}
+
+#[derive(Subcommand)]
+enum UserCmd {
+    /// Add a user or replace its password, the password is read from stdin
+    Add {
+        username: String,
+        #[clap(long)]
+        superuser: bool,
+    },
+    /// Delete a user
+    Del { username: String },
+    /// List users
+    List,
 }
 
 pub fn parse() {
@@ -35,5 +58,66 @@ pub fn parse() {
         SubCmd::Restart => Server::restart(),
         SubCmd::Reload => Server::reload(),
         SubCmd::Status => Server::status(),
+        SubCmd::User { command } => user(command),
+    }
+}
+
+// Edit the built-in user file, a running server picks up the changes
+fn user(command: UserCmd) {
+    let password = match &command {
+        UserCmd::Add { .. } => match read_password() {
+            Ok(password) if !password.is_empty() => password,
+            Ok(_) => fail("password must not be empty"),
+            Err(e) => fail(format!("password: {}", e)),
+        },
+        _ => String::new(),
+    };
+    let cfg = CFG.blocking_read().auth.file.clone();
+    let result = Users::load(cfg).and_then(|users| match command {
+        UserCmd::Add { username, superuser } => {
+            users.add(&username, &password, superuser).map(|_| println!("user {} saved", username))
+        }
+        UserCmd::Del { username } => users.delete(&username).map(|deleted| match deleted {
+            true => println!("user {} deleted", username),
+            false => println!("user {} not found", username),
+        }),
+        UserCmd::List => {
+            for user in users.list() {
+                let role = if user.is_superuser { " (superuser)" } else { "" };
+                println!("{}{}", user.username, role);
+            }
+            Ok(())
+        }
+    });
+    if let Err(e) = result {
+        fail(e);
+    }
+}
+
+fn fail(e: impl std::fmt::Display) -> ! {
+    eprintln!("{}", e);
+    exit(1)
+}
+
+// One line from stdin, prompted for without echo on a terminal
+fn read_password() -> io::Result<String> {
+    let stdin = io::stdin();
+    let saved = if stdin.is_terminal() {
+        eprint!("Password: ");
+        let saved = tcgetattr(&stdin)?;
+        let mut quiet = saved.clone();
+        quiet.local_flags.remove(LocalFlags::ECHO);
+        tcsetattr(&stdin, SetArg::TCSANOW, &quiet)?;
+        Some(saved)
+    } else {
+        None
+    };
+    let mut line = String::new();
+    let read = stdin.read_line(&mut line);
+    if let Some(saved) = saved {
+        tcsetattr(&stdin, SetArg::TCSANOW, &saved)?;
+        eprintln!();
     }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
 }
@@ -1,9 +1,16 @@
+use crate::Acl;
+use crate::Auth;
+use crate::AutoSubscribe;
 use crate::Log;
+use crate::Rewrite;
+use crate::Sys;
 use crate::Web;
+use crate::{BannedConfig, DelayedConfig, FlappingConfig, Limiter, SocketOptions};
 use config::{Environment, File};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
+use std::fmt;
 use std::net::SocketAddr;
 use std::process::exit;
 use std::str::FromStr;
@@ -17,37 +24,223 @@ pub enum ConfigError {
     NotFound(String),
     #[error("config deserialize error: {0}")]
     ConfigError(#[from] config::ConfigError),
+    #[error("invalid config: {0}")]
+    Invalid(String),
 }
 
 // Configuration struct
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
+    // Named listeners per protocol, [listener.tcp.<name>]
     #[serde(rename = "listener")]
-    pub listeners: HashMap<Protocol, Listener>,
+    pub listeners: HashMap<Protocol, HashMap<String, Listener>>,
     pub mqtt: Mqtt,
+    #[serde(default)]
+    pub node: Node,
+    #[serde(default)]
+    pub sys: Sys,
+    #[serde(default)]
+    pub delayed: DelayedConfig,
+    #[serde(default)]
+    pub rewrite: Vec<Rewrite>,
+    #[serde(default)]
+    pub auto_subscribe: Vec<AutoSubscribe>,
+    #[serde(default)]
+    pub auth: Auth,
+    #[serde(default)]
+    pub acl: Acl,
+    #[serde(default)]
+    pub banned: BannedConfig,
+    #[serde(default)]
+    pub flapping: FlappingConfig,
+    #[serde(default)]
+    pub limiter: Limiter,
     pub log: Log,
     pub web: Web,
 }
 
+// Node configuration
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Node {
+    pub name: String,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self { name: format!("{}@127.0.0.1", env!("CARGO_PKG_NAME")) }
+    }
+}
+
 // Listener configuration
 #[derive(Debug, Deserialize, Clone)]
 pub struct Listener {
-    pub addr: SocketAddr,
+    // <protocol>:<name>, used in logs, metrics and the api
+    #[serde(skip)]
+    pub name: String,
+    // Address of tcp, tls, ws and wss listeners
+    #[serde(default)]
+    pub addr: Option<SocketAddr>,
+    // Socket file of unix listeners
+    #[serde(default)]
+    pub path: Option<String>,
+    // Permissions of the socket file, 0o660
+    #[serde(default)]
+    pub mode: Option<u32>,
+    // Use the uid of unix socket peers as the username
+    #[serde(default)]
+    pub peer_cred_as_username: bool,
+    // Accept unix socket peers without another authenticator allowing them, the kernel vouches
+    // for their uid
+    #[serde(default)]
+    pub peer_cred_is_identity: bool,
     #[serde(default)]
     pub cert: Option<String>,
     #[serde(default)]
     pub key: Option<String>,
+    // CA certificates client certificates are verified against
+    #[serde(default)]
+    pub cacert: Option<String>,
+    // Request and verify a client certificate
+    #[serde(default)]
+    pub verify_peer: bool,
+    // Reject clients without a certificate when verify_peer is set
+    #[serde(default)]
+    pub fail_if_no_peer_cert: bool,
+    #[serde(default)]
+    pub peer_cert_as_username: Option<CertField>,
+    #[serde(default)]
+    pub peer_cert_as_clientid: Option<CertField>,
+    // Accept clients with a verified certificate, or one reported in the PROXY header,
+    // without another authenticator allowing them
+    #[serde(default)]
+    pub cert_is_identity: bool,
+    // Oldest TLS version accepted, 1.2 if not set
+    #[serde(default)]
+    pub min_tls_version: Option<TlsVersion>,
+    // Cipher suites offered by IANA name, TLS13_AES_256_GCM_SHA384, all supported if empty
+    #[serde(default)]
+    pub ciphers: Vec<String>,
+    // ALPN protocols, the first one the client also offers is selected
+    #[serde(default)]
+    pub alpn: Vec<String>,
+    // Certificates picked by the SNI hostname, cert and key are served when none matches
+    #[serde(default)]
+    pub sni_certs: Vec<SniCert>,
+    // Override the global limiter for clients of this listener
+    #[serde(default)]
+    pub messages_rate: Option<u32>,
+    #[serde(default)]
+    pub bytes_rate: Option<u32>,
+    // Seconds a new connection has to finish the TLS and WebSocket handshakes and send CONNECT
+    #[serde(default = "Listener::default_connect_timeout")]
+    pub connect_timeout: u64,
+    // Connections accepted per second, unlimited if not set
+    #[serde(default)]
+    pub max_conn_rate: Option<u32>,
+    // TCP socket options, [listener.tcp.<name>.socket]
+    #[serde(default)]
+    pub socket: SocketOptions,
+    // WebSocket URL path
+    #[serde(default = "Listener::default_ws_path")]
+    pub ws_path: String,
+    // WebSocket subprotocols accepted, in the order clients offer them
+    #[serde(default = "Listener::default_ws_subprotocols")]
+    pub ws_subprotocols: Vec<String>,
+    // Origin header values allowed, any if empty
+    #[serde(default)]
+    pub ws_allowed_origins: Vec<String>,
+    // Expect a HAProxy PROXY protocol v1 or v2 header before anything else
     #[serde(default)]
     pub proxy_protocol: bool,
+    // Seconds to wait for the PROXY header
+    #[serde(default = "Listener::default_proxy_protocol_timeout")]
+    pub proxy_protocol_timeout: u64,
+    // Concurrent connections, more are closed right after accept, 0 is unlimited
     #[serde(default)]
     pub max_connections: usize,
 }
 
+impl Listener {
+    fn default_connect_timeout() -> u64 {
+        15
+    }
+
+    fn default_proxy_protocol_timeout() -> u64 {
+        3
+    }
+
+    fn default_ws_path() -> String {
+        "/mqtt".to_string()
+    }
+
+    fn default_ws_subprotocols() -> Vec<String> {
+        vec!["mqtt".to_string(), "mqttv3.1".to_string(), "mqttv3.1.1".to_string()]
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+// Certificate and key served to clients asking for server_name, "*.example.com" for a wildcard
+#[derive(Debug, Deserialize, Clone)]
+pub struct SniCert {
+    pub server_name: String,
+    pub cert: String,
+    pub key: String,
+}
+
+// Client certificate field used as the username or client id
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CertField {
+    Cn,
+    Dn,
+    San,
+}
+
 // Mqtt configuration
 #[derive(Debug, Deserialize, Clone)]
 pub struct Mqtt {
+    #[serde(default = "Mqtt::default_max_clientid_len")]
     pub max_clientid_len: u16,
     pub max_packet_size: u32,
+    #[serde(default = "Mqtt::default_max_qos")]
+    pub max_qos: u8,
+    #[serde(default = "Mqtt::default_retain_available")]
+    pub retain_available: bool,
+    #[serde(default = "Mqtt::default_max_mqueue_len")]
+    pub max_mqueue_len: usize,
+    // QoS 1 and 2 messages sent to a client and not yet acknowledged, more are queued
+    #[serde(default = "Mqtt::default_max_receive")]
+    pub max_receive: u16,
+}
+
+impl Mqtt {
+    fn default_max_clientid_len() -> u16 {
+        u16::MAX
+    }
+
+    fn default_max_qos() -> u8 {
+        2
+    }
+
+    fn default_retain_available() -> bool {
+        true
+    }
+
+    fn default_max_mqueue_len() -> usize {
+        1000
+    }
+
+    fn default_max_receive() -> u16 {
+        2048
+    }
 }
 
 // Listener protocol
@@ -57,6 +250,7 @@ pub enum Protocol {
     Tls,
     Ws,
     Wss,
+    Unix,
 }
 
 impl FromStr for Protocol {
@@ -67,11 +261,25 @@ impl FromStr for Protocol {
             "tls" => Ok(Protocol::Tls),
             "ws" => Ok(Protocol::Ws),
             "wss" => Ok(Protocol::Wss),
+            "unix" => Ok(Protocol::Unix),
             _ => Err(format!("unknown protocol: {}", s)),
         }
     }
 }
 
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Tls => "tls",
+            Protocol::Ws => "ws",
+            Protocol::Wss => "wss",
+            Protocol::Unix => "unix",
+        };
+        f.write_str(name)
+    }
+}
+
 impl<'de> Deserialize<'de> for Protocol {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -109,11 +317,15 @@ impl Config {
 
         builder =
             builder.add_source(Environment::with_prefix("iotmq").separator("__").try_parsing(true));
-        builder.build()?.try_deserialize().map_err(|e| e.into())
+        let mut config: Self = builder.build()?.try_deserialize()?;
+        for (protocol, listeners) in config.listeners.iter_mut() {
+            for (name, listener) in listeners.iter_mut() {
+                listener.name = format!("{}:{}", protocol, name);
+            }
+        }
+        Ok(config)
     }
 
-    pub fn reload() {}
-
     pub async fn get() -> Self {
         CFG.read().await.clone()
     }
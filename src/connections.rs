@@ -0,0 +1,99 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+// Active connections of one listener
+#[derive(Debug)]
+pub struct Counter {
+    addr: String,
+    current: AtomicUsize,
+    // 0 is unlimited
+    max: usize,
+}
+
+impl Counter {
+    // Count a new connection, None if the listener is full
+    pub fn acquire(self: &Arc<Self>) -> Option<Conn> {
+        self.current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (self.max == 0 || n < self.max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Conn(self.clone()))
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+// Counted connection, released when dropped
+pub struct Conn(Arc<Counter>);
+
+impl Drop for Conn {
+    fn drop(&mut self) {
+        self.0.current.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListenerStats {
+    pub name: String,
+    // Address or socket path
+    pub addr: String,
+    pub current_connections: usize,
+    pub max_connections: usize,
+}
+
+// Connection counts of all listeners
+#[derive(Default)]
+pub struct Connections {
+    listeners: RwLock<BTreeMap<String, Arc<Counter>>>,
+}
+
+impl Connections {
+    pub fn register(&self, name: &str, addr: String, max: usize) -> Arc<Counter> {
+        let counter = Arc::new(Counter { addr, current: AtomicUsize::new(0), max });
+        self.listeners.write().unwrap().insert(name.to_string(), counter.clone());
+        counter
+    }
+
+    pub fn stats(&self) -> Vec<ListenerStats> {
+        let listeners = self.listeners.read().unwrap();
+        listeners
+            .iter()
+            .map(|(name, counter)| ListenerStats {
+                name: name.clone(),
+                addr: counter.addr.clone(),
+                current_connections: counter.current.load(Ordering::Acquire),
+                max_connections: counter.max,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter() {
+        let connections = Connections::default();
+        let counter = connections.register("tcp", "127.0.0.1:1883".into(), 2);
+
+        let first = counter.acquire().unwrap();
+        let _second = counter.acquire().unwrap();
+        assert!(counter.acquire().is_none());
+        assert_eq!(connections.stats()[0].current_connections, 2);
+
+        // Closing a connection makes room for the next one
+        drop(first);
+        assert!(counter.acquire().is_some());
+        assert_eq!(connections.stats()[0].current_connections, 1);
+
+        let unlimited = connections.register("ws", "127.0.0.1:8083".into(), 0);
+        let conns: Vec<_> = (0..100).map(|_| unlimited.acquire().unwrap()).collect();
+        assert_eq!(conns.len(), 100);
+    }
+}
@@ -1,23 +1,79 @@
+use crate::acl::Rules;
+use crate::auth::jwt::Jwt;
+use crate::auth::users::Users;
+use crate::auth::AuthChain;
+use crate::banned::Banned;
+use crate::broker::Broker;
+use crate::connections::Connections;
+use crate::delayed::Delayed;
+use crate::flapping::Flapping;
+use crate::plugins::Plugins;
 use crate::{Config, CFG};
+use std::fmt::Display;
+use std::process::exit;
 use std::sync::Arc;
 use tokio::sync::{broadcast, RwLock};
 
+// Print the error and exit, for startup failures
+fn or_exit<T, E: Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1)
+    })
+}
+
 // Shared context
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Context(Arc<ContextInner>);
 
 // Internal shared context
-#[derive(Debug)]
 struct ContextInner {
     shutdown_tx: broadcast::Sender<()>,
+    // Reload certificates on SIGHUP
+    reload_tx: broadcast::Sender<()>,
     cfg: Arc<RwLock<Config>>,
+    broker: Broker,
+    delayed: Delayed,
+    auth: AuthChain,
+    users: Arc<Users>,
+    acl: Rules,
+    banned: Banned,
+    flapping: Flapping,
+    connections: Connections,
 }
 
 impl Context {
-    pub fn new() -> Self {
+    pub async fn new() -> Self {
         let (shutdown_tx, _) = broadcast::channel::<()>(1);
+        let (reload_tx, _) = broadcast::channel::<()>(1);
         let cfg = CFG.clone();
-        Self(Arc::new(ContextInner { shutdown_tx, cfg }))
+        let broker = Broker::new();
+        let delayed = Delayed::new();
+        let (auth_cfg, acl_cfg, banned_cfg) = {
+            let cfg = cfg.read().await;
+            (cfg.auth.clone(), cfg.acl.clone(), cfg.banned.clone())
+        };
+        let users = Arc::new(or_exit(Users::load(auth_cfg.file)));
+        let jwt = or_exit(Jwt::new(auth_cfg.jwt));
+        let plugins = or_exit(Plugins::load());
+        let auth = AuthChain::new(plugins, users.clone(), jwt);
+        let acl =
+            if acl_cfg.enable { or_exit(Rules::load(&acl_cfg.file)) } else { Rules::default() };
+        let banned = or_exit(Banned::load(banned_cfg));
+        let flapping = Flapping::new();
+        Self(Arc::new(ContextInner {
+            shutdown_tx,
+            reload_tx,
+            cfg,
+            broker,
+            delayed,
+            auth,
+            users,
+            acl,
+            banned,
+            flapping,
+            connections: Connections::default(),
+        }))
     }
 
     pub fn subscribe(&self) -> broadcast::Receiver<()> {
@@ -28,7 +84,47 @@ impl Context {
         let _ = self.0.shutdown_tx.send(());
     }
 
+    pub fn subscribe_reload(&self) -> broadcast::Receiver<()> {
+        self.0.reload_tx.subscribe()
+    }
+
+    pub fn reload(&self) {
+        let _ = self.0.reload_tx.send(());
+    }
+
     pub async fn config(&self) -> Config {
         self.0.cfg.read().await.clone()
     }
+
+    pub fn broker(&self) -> &Broker {
+        &self.0.broker
+    }
+
+    pub fn delayed(&self) -> &Delayed {
+        &self.0.delayed
+    }
+
+    pub fn auth(&self) -> &AuthChain {
+        &self.0.auth
+    }
+
+    pub fn users(&self) -> &Users {
+        &self.0.users
+    }
+
+    pub fn acl(&self) -> &Rules {
+        &self.0.acl
+    }
+
+    pub fn banned(&self) -> &Banned {
+        &self.0.banned
+    }
+
+    pub fn flapping(&self) -> &Flapping {
+        &self.0.flapping
+    }
+
+    pub fn connections(&self) -> &Connections {
+        &self.0.connections
+    }
 }
@@ -0,0 +1,217 @@
+use crate::broker::Message;
+use crate::Context;
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{Mutex, Notify};
+use tokio::time::{sleep_until, Duration, Instant};
+use tracing::{debug, info};
+
+pub const PREFIX: &str = "$delayed/";
+
+// Delayed publish configuration
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct DelayedConfig {
+    pub enable: bool,
+    // Maximum number of pending delayed messages, 0 means unlimited
+    pub max_delayed_messages: usize,
+}
+
+impl Default for DelayedConfig {
+    fn default() -> Self {
+        Self { enable: true, max_delayed_messages: 10000 }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DelayedError {
+    #[error("invalid delayed topic {0:?}, expected $delayed/<seconds>/<topic>")]
+    InvalidTopic(String),
+    #[error("delayed message queue is full, maximum {0}")]
+    Full(usize),
+}
+
+#[derive(Debug)]
+struct Entry {
+    msg: Message,
+    delay: u32,
+    publish_at: DateTime<Local>,
+    due: Instant,
+}
+
+// Pending messages by id with an index ordered by due time
+#[derive(Debug, Default)]
+struct Queue {
+    entries: HashMap<u64, Entry>,
+    order: BTreeSet<(Instant, u64)>,
+}
+
+// Pending delayed message as returned by the api
+#[derive(Debug, Serialize)]
+pub struct Pending {
+    pub id: u64,
+    pub from: String,
+    pub topic: String,
+    pub qos: u8,
+    pub payload: String,
+    pub delayed_interval: u32,
+    pub publish_at: String,
+    pub expected_at: String,
+}
+
+// Delayed messages ordered by due time
+pub struct Delayed {
+    queue: Mutex<Queue>,
+    seq: AtomicU64,
+    notify: Notify,
+}
+
+impl Delayed {
+    pub fn new() -> Self {
+        Self { queue: Mutex::new(Queue::default()), seq: AtomicU64::new(0), notify: Notify::new() }
+    }
+
+    // Parse $delayed/<seconds>/<topic> into the delay and the real topic
+    pub fn parse(topic: &str) -> Result<(u32, &str), DelayedError> {
+        let invalid = || DelayedError::InvalidTopic(topic.to_string());
+        let rest = topic.strip_prefix(PREFIX).ok_or_else(invalid)?;
+        let (delay, topic) = rest.split_once('/').ok_or_else(invalid)?;
+        let delay = delay.parse::<u32>().map_err(|_| invalid())?;
+        if topic.is_empty() || topic.starts_with('$') {
+            return Err(invalid());
+        }
+        Ok((delay, topic))
+    }
+
+    // Queue a message to be published to its topic after the delay in seconds,
+    // at most max messages are pending, 0 is unlimited
+    pub async fn push(&self, msg: Message, delay: u32, max: usize) -> Result<u64, DelayedError> {
+        let mut queue = self.queue.lock().await;
+        if max > 0 && queue.entries.len() >= max {
+            return Err(DelayedError::Full(max));
+        }
+
+        let id = self.seq.fetch_add(1, Ordering::Relaxed);
+        let due = Instant::now() + Duration::from_secs(delay as u64);
+        queue.entries.insert(id, Entry { msg, delay, publish_at: Local::now(), due });
+        queue.order.insert((due, id));
+        drop(queue);
+
+        self.notify.notify_one();
+        Ok(id)
+    }
+
+    pub async fn list(&self) -> Vec<Pending> {
+        let queue = self.queue.lock().await;
+        queue
+            .order
+            .iter()
+            .map(|(_, id)| {
+                let entry = &queue.entries[id];
+                let expected_at = entry.publish_at + TimeDelta::seconds(entry.delay as i64);
+                Pending {
+                    id: *id,
+                    from: entry.msg.from.clone(),
+                    topic: entry.msg.topic.clone(),
+                    qos: entry.msg.qos as u8,
+                    payload: String::from_utf8_lossy(&entry.msg.payload).to_string(),
+                    delayed_interval: entry.delay,
+                    publish_at: entry.publish_at.to_rfc3339(),
+                    expected_at: expected_at.to_rfc3339(),
+                }
+            })
+            .collect()
+    }
+
+    // Remove a pending message, returns whether it existed
+    pub async fn delete(&self, id: u64) -> bool {
+        let mut queue = self.queue.lock().await;
+        match queue.entries.remove(&id) {
+            Some(entry) => queue.order.remove(&(entry.due, id)),
+            None => false,
+        }
+    }
+
+    // Take the messages that are due and the due time of the next one
+    async fn due(&self) -> (Vec<Message>, Option<Instant>) {
+        let mut queue = self.queue.lock().await;
+        let now = Instant::now();
+        let mut msgs = Vec::new();
+        while let Some(&(due, id)) = queue.order.first() {
+            if due > now {
+                return (msgs, Some(due));
+            }
+            queue.order.pop_first();
+            msgs.extend(queue.entries.remove(&id).map(|entry| entry.msg));
+        }
+        (msgs, None)
+    }
+}
+
+// Deliver delayed messages when they are due
+pub async fn run(ctx: Context) {
+    info!("Delayed publish scheduler started");
+
+    let delayed = ctx.delayed();
+    let mut shutdown = ctx.subscribe();
+    loop {
+        let (msgs, next) = delayed.due().await;
+        for msg in msgs {
+            debug!("Delayed message to {} is due", msg.topic);
+            ctx.broker().publish(msg).await;
+        }
+
+        let next = next.unwrap_or_else(|| Instant::now() + Duration::from_secs(3600));
+        tokio::select! {
+            _ = sleep_until(next) => {}
+            _ = delayed.notify.notified() => {}
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Delayed::parse("$delayed/10/a/b").unwrap(), (10, "a/b"));
+        assert_eq!(Delayed::parse("$delayed/0/a").unwrap(), (0, "a"));
+        assert!(Delayed::parse("$delayed/a/b").is_err());
+        assert!(Delayed::parse("$delayed/-1/a").is_err());
+        assert!(Delayed::parse("$delayed/10").is_err());
+        assert!(Delayed::parse("$delayed/10/").is_err());
+        assert!(Delayed::parse("a/10/b").is_err());
+        // The real topic must not be another $ topic
+        assert!(Delayed::parse("$delayed/10/$SYS/a").is_err());
+        assert!(Delayed::parse("$delayed/10/$delayed/10/a").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let delayed = Delayed::new();
+        let first = delayed.push(Message::new("a", "1"), 0, 2).await.unwrap();
+        let second = delayed.push(Message::new("b", "2"), 60, 2).await.unwrap();
+        assert!(matches!(
+            delayed.push(Message::new("c", "3"), 0, 2).await,
+            Err(DelayedError::Full(2))
+        ));
+        let topics: Vec<_> = delayed.list().await.into_iter().map(|p| p.topic).collect();
+        assert_eq!(topics, ["a", "b"]);
+
+        // Deleting makes room for the next one
+        assert!(delayed.delete(second).await);
+        assert!(!delayed.delete(second).await);
+        delayed.push(Message::new("c", "3"), 30, 2).await.unwrap();
+
+        let (msgs, next) = delayed.due().await;
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].topic, "a");
+        assert!(next.is_some());
+        assert!(!delayed.delete(first).await);
+        assert_eq!(delayed.list().await.len(), 1);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::banned::{Ban, BanAs};
+use crate::{sys, Context};
+use chrono::{Local, TimeDelta};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use tracing::{error, warn};
+
+// Flapping detection configuration
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct FlappingConfig {
+    pub enable: bool,
+    // Connections allowed per client id within the window, more bans the client
+    pub max_count: usize,
+    // Sliding window in seconds
+    pub window_time: u64,
+    // Seconds a flapping client is banned
+    pub ban_time: u64,
+}
+
+impl Default for FlappingConfig {
+    fn default() -> Self {
+        Self { enable: false, max_count: 15, window_time: 60, ban_time: 300 }
+    }
+}
+
+struct Inner {
+    // Connection times per client id within the window
+    clients: HashMap<String, VecDeque<Instant>>,
+    purged: Instant,
+}
+
+// Connection counts per client id in a sliding window
+pub struct Flapping {
+    inner: Mutex<Inner>,
+}
+
+impl Flapping {
+    pub fn new() -> Self {
+        Self { inner: Mutex::new(Inner { clients: HashMap::new(), purged: Instant::now() }) }
+    }
+
+    // Record a connection, returns whether the client exceeded the threshold
+    fn connected(&self, cfg: &FlappingConfig, client_id: &str, now: Instant) -> bool {
+        let window = Duration::from_secs(cfg.window_time);
+        let mut inner = self.inner.lock().unwrap();
+
+        // Drop clients that stopped connecting once per window
+        if now.duration_since(inner.purged) >= window {
+            inner.clients.retain(|_, times| times.back().is_some_and(|t| now - *t < window));
+            inner.purged = now;
+        }
+
+        let times = inner.clients.entry(client_id.to_string()).or_default();
+        while times.front().is_some_and(|t| now - *t >= window) {
+            times.pop_front();
+        }
+        times.push_back(now);
+        if times.len() <= cfg.max_count {
+            return false;
+        }
+        inner.clients.remove(client_id);
+        true
+    }
+}
+
+// Ban the client if it reconnects too often, returns the ban
+pub async fn detect(ctx: &Context, client_id: &str, addr: SocketAddr) -> Option<Ban> {
+    let cfg = ctx.config().await.flapping;
+    if !cfg.enable || !ctx.flapping().connected(&cfg, client_id, Instant::now()) {
+        return None;
+    }
+
+    let reason = format!("more than {} connections in {}s", cfg.max_count, cfg.window_time);
+    warn!(
+        "Client {} from {} is flapping, {}, banned for {}s",
+        client_id, addr, reason, cfg.ban_time
+    );
+    let ban = Ban {
+        kind: BanAs::ClientId,
+        who: client_id.to_string(),
+        reason: format!("flapping, {}", reason),
+        at: Local::now(),
+        until: Some(Local::now() + TimeDelta::seconds(cfg.ban_time as i64)),
+    };
+    if let Err(e) = ctx.banned().add(ban.clone()) {
+        error!("Ban flapping client {}: {}", client_id, e);
+    }
+
+    let payload = json!({
+        "ipaddress": addr.to_string(),
+        "max_count": cfg.max_count,
+        "window_time": cfg.window_time,
+        "ban_time": cfg.ban_time,
+    });
+    sys::event(ctx, client_id, "flapping", payload).await;
+    Some(ban)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flapping() {
+        let cfg =
+            FlappingConfig { enable: true, max_count: 3, window_time: 10, ..Default::default() };
+        let flapping = Flapping::new();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // Connections sliding out of the window are not counted
+        for secs in [0, 4, 8, 12, 16] {
+            assert!(!flapping.connected(&cfg, "c1", at(secs)));
+        }
+        assert!(!flapping.connected(&cfg, "c2", at(16)));
+        assert!(flapping.connected(&cfg, "c1", at(17)));
+        // History is reset after the client is banned
+        assert!(!flapping.connected(&cfg, "c1", at(18)));
+
+        // Idle clients are purged
+        flapping.connected(&cfg, "c3", at(40));
+        let inner = flapping.inner.lock().unwrap();
+        assert_eq!(inner.clients.keys().collect::<Vec<_>>(), ["c3"]);
+    }
+}
@@ -1,24 +1,49 @@
 pub mod cmd;
 
+mod acl;
 mod api;
+mod auth;
+mod auto_subscribe;
+mod banned;
+mod broker;
 mod config;
+mod connections;
 mod context;
+mod delayed;
+mod flapping;
+mod limiter;
 mod log;
+mod metrics;
 mod mqtt;
 mod plugins;
 mod protocol;
+mod proxy;
+mod rewrite;
 mod server;
 mod session;
+mod socket;
 mod stream;
+mod sys;
+mod tls;
 mod web;
 
+use acl::Acl;
+use auth::Auth;
+use auto_subscribe::AutoSubscribe;
+use banned::BannedConfig;
 use config::{Config, Listener as ListenerConfig, Protocol, CFG};
 use context::Context;
+use delayed::DelayedConfig;
+use flapping::FlappingConfig;
+use limiter::Limiter;
 use log::Log;
 use mqtt::MqttServer;
+use rewrite::Rewrite;
 use server::Server;
 use session::Session;
+use socket::SocketOptions;
 use stream::Stream;
+use sys::Sys;
 use web::{Web, WebServer};
 
 #[derive(thiserror::Error, Debug)]
@@ -0,0 +1,97 @@
+use serde::Deserialize;
+use tokio::time::{sleep_until, Duration, Instant};
+
+// Per connection rate limits, listeners may override them, 0 is unlimited
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Limiter {
+    // PUBLISH packets read per second
+    pub messages_rate: u32,
+    // Bytes read per second
+    pub bytes_rate: u32,
+}
+
+// Token bucket refilled at rate per second holding at most one second of tokens
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32) -> Option<Self> {
+        let rate = rate as f64;
+        (rate > 0.0).then(|| Self { rate, tokens: rate, updated: Instant::now() })
+    }
+
+    // Take n tokens, the bucket may go into debt, returns when the debt is repaid
+    fn take(&mut self, n: u32, now: Instant) -> Instant {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - n as f64;
+        self.updated = now;
+        if self.tokens >= 0.0 {
+            return now;
+        }
+        now + Duration::from_secs_f64(-self.tokens / self.rate)
+    }
+}
+
+// Counted and byte rate limits, the reader waits instead of dropping anything
+#[derive(Debug, Default)]
+pub struct RateLimit {
+    count: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    resume_at: Option<Instant>,
+}
+
+impl RateLimit {
+    pub fn new(rate: u32, bytes_rate: u32) -> Self {
+        Self { count: TokenBucket::new(rate), bytes: TokenBucket::new(bytes_rate), resume_at: None }
+    }
+
+    // Account for what was read
+    pub fn consume(&mut self, count: u32, bytes: u32) {
+        let now = Instant::now();
+        let count = self.count.as_mut().filter(|_| count > 0).map(|b| b.take(count, now));
+        let bytes = self.bytes.as_mut().map(|b| b.take(bytes, now));
+        self.resume_at = count.max(bytes).filter(|at| *at > now).max(self.resume_at);
+    }
+
+    // When reading may resume, None if it is not paused
+    pub fn resume_at(&self) -> Option<Instant> {
+        self.resume_at
+    }
+
+    // Wait until reading may resume, cancel safe
+    pub async fn wait(&mut self) {
+        if let Some(resume_at) = self.resume_at {
+            sleep_until(resume_at).await;
+            self.resume_at = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10).unwrap();
+        bucket.updated = start;
+        assert!(TokenBucket::new(0).is_none());
+
+        // A second of burst, then one token per 100ms
+        assert_eq!(bucket.take(10, start), start);
+        assert_eq!(bucket.take(5, start), start + Duration::from_millis(500));
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.take(1, later), later + Duration::from_millis(100));
+
+        // Refills up to the capacity only
+        let idle = later + Duration::from_secs(60);
+        assert_eq!(bucket.take(10, idle), idle);
+        assert!(bucket.take(1, idle) > idle);
+    }
+}
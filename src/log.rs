@@ -74,7 +74,7 @@ impl FormatTime for Timer {
 
 // Log initialization
 pub async fn init() {
-    if !LOG_GUARD.get().is_none() {
+    if LOG_GUARD.get().is_some() {
         return;
     }
 
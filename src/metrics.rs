@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+// Broker metrics
+#[derive(Debug)]
+pub struct Metrics {
+    start: Instant,
+    pub clients_connected: AtomicU64,
+    pub clients_disconnected: AtomicU64,
+    // Connections closed for not sending CONNECT within the listener connect_timeout
+    pub clients_connect_timeout: AtomicU64,
+    pub messages_received: AtomicU64,
+    pub messages_sent: AtomicU64,
+    pub messages_dropped: AtomicU64,
+    pub bytes_received: AtomicU64,
+    pub bytes_sent: AtomicU64,
+}
+
+// Point in time copy of the metrics
+#[derive(Debug)]
+pub struct Snapshot {
+    pub uptime: u64,
+    pub clients_connected: u64,
+    pub clients_disconnected: u64,
+    pub clients_connect_timeout: u64,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub messages_dropped: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            clients_connected: AtomicU64::new(0),
+            clients_disconnected: AtomicU64::new(0),
+            clients_connect_timeout: AtomicU64::new(0),
+            messages_received: AtomicU64::new(0),
+            messages_sent: AtomicU64::new(0),
+            messages_dropped: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    // Seconds since the broker started
+    pub fn uptime(&self) -> u64 {
+        self.start.elapsed().as_secs()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            uptime: self.uptime(),
+            clients_connected: self.clients_connected.load(Ordering::Relaxed),
+            clients_disconnected: self.clients_disconnected.load(Ordering::Relaxed),
+            clients_connect_timeout: self.clients_connect_timeout.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_dropped: self.messages_dropped.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -1,79 +1,191 @@
-use crate::{Context, Error, ListenerConfig, Protocol, Stream};
+use crate::auth::{users, PeerCert, PeerCred};
+use crate::connections::Counter;
+use crate::limiter::RateLimit;
+use crate::metrics::Metrics;
+use crate::tls::{self, SniResolver};
+use crate::{delayed, proxy, socket, sys, Context, Error, ListenerConfig, Protocol, Stream};
 use anyhow::anyhow;
 use async_tungstenite::tokio::accept_hdr_async;
 use async_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
-use async_tungstenite::tungstenite::http::HeaderValue;
+use async_tungstenite::tungstenite::http::header::{ORIGIN, SEC_WEBSOCKET_PROTOCOL};
+use async_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
 use futures::future::join_all;
+use nix::sys::stat::{umask, Mode};
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::task::{self, Poll};
+use sync_wrapper::SyncWrapper;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::time::{sleep, timeout, timeout_at, Duration, Instant};
+use tokio_rustls::rustls::crypto::CryptoProvider;
 use tokio_rustls::rustls::pki_types::pem::PemObject;
-use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::server::danger::ClientCertVerifier;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
 use tokio_rustls::TlsAcceptor;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+use ws_stream_tungstenite::WsStream;
+
+// Unix socket peers have no IP address, they are reported as 0.0.0.0 so that ipaddr rules
+// and bans meant for loopback TCP clients do not apply to them
+const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+// Pause after a failed accept, running out of file descriptors lasts until connections close
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
 #[derive(Debug)]
-struct Listener {
+struct Listener<L = TcpListener> {
     protocol: Protocol,
-    listener: TcpListener,
+    listener: L,
     config: Arc<ListenerConfig>,
+    connections: Arc<Counter>,
 }
 
-impl Listener {
-    async fn tcp(&self, ctx: Context) -> Result<(), Error> {
-        info!("MqttServer TCP listening on {}", self.config.addr);
+// Listening socket of a listener
+trait Accept {
+    type Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
 
-        let mut shutdown = ctx.subscribe();
-        loop {
-            tokio::select! {
-                res = self.listener.accept() => {
-                    let (stream, addr) = match res {
-                        Ok((stream, addr)) => {
-                            debug!("TCP accepted new connection from {}", addr);
-                            (stream, addr)
-                        }
-                        Err(_) => continue
-                    };
+    // Address or socket path for logs
+    fn local(&self) -> io::Result<String>;
 
-                    tokio::spawn(async move {
-                        match Stream::handshake(stream, addr).await {
-                            Ok(session) => session.run().await,
-                            Err(err) => debug!("Handshake failed: {:?}", err)
-                        }
-                    });
-                }
-                _ = shutdown.recv() => {
-                    break;
-                }
-            }
+    // Accept a connection, with the client address and the credentials of unix socket peers
+    fn accept_conn(
+        &self,
+        config: &ListenerConfig,
+    ) -> impl Future<Output = io::Result<(Self::Stream, SocketAddr, Option<PeerCred>)>> + Send;
+}
+
+impl Accept for TcpListener {
+    type Stream = TcpStream;
+
+    fn local(&self) -> io::Result<String> {
+        self.local_addr().map(|addr| addr.to_string())
+    }
+
+    async fn accept_conn(
+        &self,
+        config: &ListenerConfig,
+    ) -> io::Result<(TcpStream, SocketAddr, Option<PeerCred>)> {
+        let (stream, addr) = self.accept().await?;
+        if let Err(e) = socket::configure(&stream, &config.socket) {
+            debug!("Socket options of {} not set: {}", addr, e);
         }
+        Ok((stream, addr, None))
+    }
+}
 
-        Ok(())
+impl Accept for UnixListener {
+    type Stream = UnixStream;
+
+    fn local(&self) -> io::Result<String> {
+        let addr = self.local_addr()?;
+        Ok(addr.as_pathname().map(|path| path.display().to_string()).unwrap_or_default())
     }
 
-    async fn tls(&self, ctx: Context) -> Result<(), Error> {
-        let acceptor = self.acceptor()?;
+    async fn accept_conn(
+        &self,
+        _config: &ListenerConfig,
+    ) -> io::Result<(UnixStream, SocketAddr, Option<PeerCred>)> {
+        let (stream, _) = self.accept().await?;
+        let peer_cred =
+            stream.peer_cred().ok().map(|cred| PeerCred { uid: cred.uid(), gid: cred.gid() });
+        Ok((stream, UNIX_PEER, peer_cred))
+    }
+}
+
+// Accepted connection, upgraded to MQTT in its own task
+struct Accepted<S> {
+    stream: S,
+    addr: SocketAddr,
+    peer_cred: Option<PeerCred>,
+    ctx: Context,
+    config: Arc<ListenerConfig>,
+    // The TLS/WS handshakes and CONNECT must be done by then
+    deadline: Instant,
+    // Current acceptor of tls and wss listeners
+    acceptor: Option<TlsAcceptor>,
+}
+
+impl<L: Accept> Listener<L> {
+    // Accept the next connection, paced by max_conn_rate
+    async fn accept(
+        &self,
+        limit: &mut RateLimit,
+    ) -> io::Result<(L::Stream, SocketAddr, Option<PeerCred>)> {
+        limit.wait().await;
+        let res = self.listener.accept_conn(&self.config).await;
+        if res.is_ok() {
+            limit.consume(1, 0);
+        }
+        res
+    }
 
-        info!("MqttServer TLS listening on {}", self.config.addr);
+    // Accept connections until shutdown and hand each one to the protocol upgrade,
+    // tls and wss listeners load their certificates again on reload
+    async fn accept_loop<F, Fut>(&self, ctx: Context, upgrade: F) -> Result<(), Error>
+    where
+        F: Fn(Accepted<L::Stream>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let tls = matches!(self.protocol, Protocol::Tls | Protocol::Wss);
+        let mut acceptor = if tls { Some(self.acceptor()?) } else { None };
+
+        info!("MqttServer[{}] listening on {}", self.config.name, self.listener.local()?);
 
         let mut shutdown_rx = ctx.subscribe();
+        let mut reload_rx = ctx.subscribe_reload();
+        let mut accept_limit = RateLimit::new(self.config.max_conn_rate.unwrap_or(0), 0);
         loop {
             tokio::select! {
-                res = self.listener.accept() => {
-                    let (stream,addr) = match res {
-                        Ok((stream,addr)) => {
-                            debug!("TLS accepted new connection from {}", addr);
-                            (stream,addr)
+                res = self.accept(&mut accept_limit) => {
+                    let (stream, addr, peer_cred) = match res {
+                        Ok((_, addr, _)) if ctx.banned().peerhost(addr.ip()) => {
+                            debug!("Rejected connection from banned {}", addr);
+                            continue
+                        }
+                        Ok(accepted) => accepted,
+                        // Out of file descriptors or memory, wait for connections to close
+                        Err(e) => {
+                            warn!("MqttServer[{}] accept failed: {}", self.config.name, e);
+                            sleep(ACCEPT_BACKOFF).await;
+                            continue
                         }
-                        Err(_) => continue
                     };
-
-                    let _stream = match acceptor.accept(stream).await {
-                        Ok(stream) => stream,
-                        Err(_) => continue
+                    let peer = peer_cred.map_or(addr.to_string(), |cred| format!("uid {}", cred.uid));
+                    debug!("{} accepted new connection from {}", self.config.name, peer);
+                    let Some(conn) = self.connections.acquire() else {
+                        debug!(
+                            "Rejected connection from {}: {} reached max_connections {}",
+                            peer, self.config.name, self.connections.max()
+                        );
+                        continue
                     };
 
-                    //
+                    // Handshakes run in the connection task so slow clients don't hold up accepting
+                    let upgraded = upgrade(Accepted {
+                        stream,
+                        addr,
+                        peer_cred,
+                        ctx: ctx.clone(),
+                        config: self.config.clone(),
+                        deadline: Instant::now() + Duration::from_secs(self.config.connect_timeout),
+                        acceptor: acceptor.clone(),
+                    });
+                    tokio::spawn(async move {
+                        let _conn = conn;
+                        upgraded.await
+                    });
+                }
+                _ = reload_rx.recv(), if tls => {
+                    acceptor = acceptor.map(|current| self.reload(current));
                 }
                 _ = shutdown_rx.recv() => {
                     break;
@@ -84,112 +196,365 @@ impl Listener {
         Ok(())
     }
 
-    async fn ws(&self, ctx: Context) -> Result<(), Error> {
-        info!("MqttServer WS listening on {}", self.config.addr);
+    // Acceptor with the certificate and key files read again, the current one if they are invalid.
+    // Established connections keep the certificate they were accepted with
+    fn reload(&self, current: TlsAcceptor) -> TlsAcceptor {
+        match self.acceptor() {
+            Ok(acceptor) => {
+                info!("MqttServer[{}] certificate reloaded", self.config.name);
+                acceptor
+            }
+            Err(e) => {
+                error!("MqttServer[{}] certificate reload failed: {}", self.config.name, e);
+                current
+            }
+        }
+    }
 
-        let mut shutdown_rx = ctx.subscribe();
-        loop {
-            tokio::select! {
-                res = self.listener.accept() => {
-                    let stream = match res {
-                        Ok((stream,addr)) => {
-                            debug!("WS accepted new connection from {}", addr);
-                            stream
-                        }
-                        Err(_) => continue
-                    };
+    fn acceptor(&self) -> Result<TlsAcceptor, Error> {
+        let name = &self.config.name;
+        let provider =
+            Arc::new(tls::provider(&self.config.ciphers).map_err(|e| anyhow!("{} {}", name, e))?);
+
+        // cert and key may be left out when every client sends a configured SNI hostname
+        let default = match (&self.config.cert, &self.config.key) {
+            (Some(cert), Some(key)) => Some(
+                tls::certified_key(cert, key, &provider).map_err(|e| anyhow!("{} {}", name, e))?,
+            ),
+            (None, None) if !self.config.sni_certs.is_empty() => None,
+            (None, _) => return Err(anyhow!("{} cert is not set", name).into()),
+            (_, None) => return Err(anyhow!("{} key is not set", name).into()),
+        };
+        let mut resolver = SniResolver::new(default);
+        for sni in &self.config.sni_certs {
+            let key = tls::certified_key(&sni.cert, &sni.key, &provider)
+                .map_err(|e| anyhow!("{} sni {}: {}", name, sni.server_name, e))?;
+            resolver.add(&sni.server_name, key);
+        }
 
-                    let _stream = match accept_hdr_async(stream, ws_callback).await {
-                        Ok(stream) => stream,
-                        Err(_) => continue
-                    };
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(tls::versions(self.config.min_tls_version))
+            .map_err(|e| anyhow!("{} {}", name, e))?;
+        let builder = if self.config.verify_peer {
+            builder.with_client_cert_verifier(self.client_verifier(provider)?)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let mut config = builder.with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = self.config.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
 
-                    //
-                }
-                _ = shutdown_rx.recv() => {
-                    break;
-                }
-            }
+    // Verify client certificates against the listener cacert
+    fn client_verifier(
+        &self,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Arc<dyn ClientCertVerifier>, Error> {
+        let cacert_file =
+            self.config.cacert.as_ref().ok_or(anyhow!("{} cacert is not set", self.config.name))?;
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(cacert_file)
+            .map_err(|e| anyhow!("{} cacert[{:?}]: {:?}", self.config.name, cacert_file, e))?
+        {
+            roots.add(cert.map_err(|e| anyhow!(e))?).map_err(|e| anyhow!(e))?;
         }
 
-        Ok(())
+        let mut builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+        if !self.config.fail_if_no_peer_cert {
+            builder = builder.allow_unauthenticated();
+        }
+        Ok(builder.build().map_err(|e| anyhow!(e))?)
+    }
+}
+
+impl Listener {
+    async fn tcp(&self, ctx: Context) -> Result<(), Error> {
+        self.accept_loop(ctx, |conn| async move {
+            let Accepted { mut stream, addr, ctx, config, deadline, .. } = conn;
+            let Some((addr, peer_cert)) = proxied(&mut stream, addr, &ctx, &config).await else {
+                return;
+            };
+            serve(stream, addr, ctx, config, peer_cert, None, deadline).await;
+        })
+        .await
+    }
+
+    async fn tls(&self, ctx: Context) -> Result<(), Error> {
+        self.accept_loop(ctx, |conn| async move {
+            let Accepted { mut stream, addr, ctx, config, deadline, acceptor, .. } = conn;
+            let Some((addr, _)) = proxied(&mut stream, addr, &ctx, &config).await else {
+                return;
+            };
+            let Some(stream) = tls_accept(acceptor, stream, addr, &ctx, deadline).await else {
+                return;
+            };
+            let peer_cert = peer_cert(&stream);
+            serve(stream, addr, ctx, config, peer_cert, None, deadline).await;
+        })
+        .await
+    }
+
+    async fn ws(&self, ctx: Context) -> Result<(), Error> {
+        self.accept_loop(ctx, |conn| async move {
+            let Accepted { mut stream, addr, ctx, config, deadline, .. } = conn;
+            let Some((addr, peer_cert)) = proxied(&mut stream, addr, &ctx, &config).await else {
+                return;
+            };
+            let Some(stream) = ws_accept(stream, addr, &ctx, &config, deadline).await else {
+                return;
+            };
+            serve(stream, addr, ctx, config, peer_cert, None, deadline).await;
+        })
+        .await
     }
 
     async fn wss(&self, ctx: Context) -> Result<(), Error> {
-        let acceptor = self.acceptor()?;
+        self.accept_loop(ctx, |conn| async move {
+            let Accepted { mut stream, addr, ctx, config, deadline, acceptor, .. } = conn;
+            let Some((addr, _)) = proxied(&mut stream, addr, &ctx, &config).await else {
+                return;
+            };
+            let Some(stream) = tls_accept(acceptor, stream, addr, &ctx, deadline).await else {
+                return;
+            };
+            let peer_cert = peer_cert(&stream);
+            let Some(stream) = ws_accept(stream, addr, &ctx, &config, deadline).await else {
+                return;
+            };
+            serve(stream, addr, ctx, config, peer_cert, None, deadline).await;
+        })
+        .await
+    }
+}
 
-        info!("MqttServer WSS listening on {}", self.config.addr);
+impl Listener<UnixListener> {
+    async fn unix(&self, ctx: Context) -> Result<(), Error> {
+        let res = self
+            .accept_loop(ctx, |conn| async move {
+                let Accepted { stream, addr, peer_cred, ctx, config, deadline, .. } = conn;
+                serve(stream, addr, ctx, config, None, peer_cred, deadline).await;
+            })
+            .await;
 
-        let mut shutdown_rx = ctx.subscribe();
-        loop {
-            tokio::select! {
-                res = self.listener.accept() => {
-                    let stream = match res {
-                        Ok((stream,addr)) => {
-                            debug!("WSS accepted new connection from {}", addr);
-                            stream
-                        }
-                        Err(_) => continue
-                    };
+        let _ = fs::remove_file(self.config.path.as_deref().unwrap_or_default());
+        res
+    }
+}
 
-                     let stream = match acceptor.accept(stream).await {
-                        Ok(stream) => stream,
-                        Err(_) => continue
-                    };
+// Client address and certificate identity a load balancer forwards in the PROXY protocol header,
+// None if the connection is dropped
+async fn proxied(
+    stream: &mut TcpStream,
+    addr: SocketAddr,
+    ctx: &Context,
+    config: &ListenerConfig,
+) -> Option<(SocketAddr, Option<PeerCert>)> {
+    if !config.proxy_protocol {
+        return Some((addr, None));
+    }
 
-                    let _stream = match accept_hdr_async(stream, ws_callback).await {
-                        Ok(stream) => stream,
-                        Err(_) => continue
-                    };
+    let wait = Duration::from_secs(config.proxy_protocol_timeout);
+    let header = match timeout(wait, proxy::read_header(stream)).await {
+        Ok(Ok(header)) => header,
+        Ok(Err(e)) => {
+            debug!("Rejected connection from {}: {}", addr, e);
+            return None;
+        }
+        Err(_) => {
+            debug!("Rejected connection from {}: no PROXY header within {:?}", addr, wait);
+            return None;
+        }
+    };
 
-                    //
-                }
-                _ = shutdown_rx.recv() => {
-                    break;
-                }
-            }
+    let client = header.source.unwrap_or(addr);
+    if ctx.banned().peerhost(client.ip()) {
+        debug!("Rejected connection from banned {} proxied by {}", client, addr);
+        return None;
+    }
+    debug!("Connection from {} proxied by {}", client, addr);
+    Some((client, header.peer_cert()))
+}
+
+// Run the MQTT handshake and the session over an established connection
+async fn serve<S>(
+    io: S,
+    addr: SocketAddr,
+    ctx: Context,
+    config: Arc<ListenerConfig>,
+    peer_cert: Option<PeerCert>,
+    peer_cred: Option<PeerCred>,
+    deadline: Instant,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match Stream::handshake(io, addr, ctx, config, peer_cert, peer_cred, deadline).await {
+        Ok(session) => session.run().await,
+        Err(err) => debug!("Handshake failed: {:?}", err),
+    }
+}
+
+// Count and log a connection closed by the connect_timeout during the TLS or WebSocket handshake
+fn timed_out(ctx: &Context, addr: SocketAddr, handshake: &str) {
+    Metrics::inc(&ctx.broker().metrics.clients_connect_timeout, 1);
+    debug!("{} handshake with {} timed out", handshake, addr);
+}
+
+// TLS handshake, None if it failed or did not finish by the deadline
+async fn tls_accept(
+    acceptor: Option<TlsAcceptor>,
+    stream: TcpStream,
+    addr: SocketAddr,
+    ctx: &Context,
+    deadline: Instant,
+) -> Option<TlsStream<TcpStream>> {
+    match timeout_at(deadline, acceptor?.accept(stream)).await {
+        Ok(Ok(stream)) => Some(stream),
+        Ok(Err(e)) => {
+            debug!("TLS handshake with {} failed: {}", addr, e);
+            None
+        }
+        Err(_) => {
+            timed_out(ctx, addr, "TLS");
+            None
         }
+    }
+}
 
-        Ok(())
+// WebSocket handshake checked by ws_callback, None if it failed or did not finish by the deadline
+async fn ws_accept<S>(
+    stream: S,
+    addr: SocketAddr,
+    ctx: &Context,
+    config: &ListenerConfig,
+    deadline: Instant,
+) -> Option<impl AsyncRead + AsyncWrite + Unpin>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    #[allow(clippy::result_large_err)]
+    let callback = |req: &Request, resp| ws_callback(config, req, resp);
+    match timeout_at(deadline, accept_hdr_async(stream, callback)).await {
+        Ok(Ok(stream)) => Some(WsIo(SyncWrapper::new(WsStream::new(stream)))),
+        Ok(Err(e)) => {
+            debug!("WS handshake with {} failed: {}", addr, e);
+            None
+        }
+        Err(_) => {
+            timed_out(ctx, addr, "WS");
+            None
+        }
     }
+}
 
-    fn acceptor(&self) -> Result<TlsAcceptor, Error> {
-        let key_file =
-            self.config.key.as_ref().ok_or(anyhow!("{:?} key is not set", self.protocol))?;
-        let key = PrivateKeyDer::from_pem_file(key_file)
-            .map_err(|e| anyhow!("{:?} key[{:?}]: {:?}", self.protocol, key_file, e))?;
-
-        let cert_file =
-            self.config.cert.as_ref().ok_or(anyhow!("{:?} cert is not set", self.protocol))?;
-        let certs = CertificateDer::pem_file_iter(cert_file)
-            .map_err(|e| anyhow!("{:?} cert[{:?}]: {:?}", self.protocol, cert_file, e))?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| anyhow!(e))?;
-
-        let config = ServerConfig::builder()
-            .with_no_client_auth()
-            .with_single_cert(certs, key)
-            .map_err(|e| anyhow!(e))?;
-        let acceptor = TlsAcceptor::from(Arc::new(config));
-        Ok(acceptor)
-    }
-}
-
-fn ws_callback(request: &Request, mut response: Response) -> Result<Response, ErrorResponse> {
-    let err = "Sec-WebSocket-Protocol header missing".to_string();
-    let protocol =
-        request.headers().get("Sec-WebSocket-Protocol").ok_or(ErrorResponse::new(Some(err)))?;
-    if protocol != "protocol" {
-        let err = format!("Sec-WebSocket-Protocol: {:?} is not supported", protocol);
-        return Err(ErrorResponse::new(Some(err)));
-    }
-    response.headers_mut().insert("sec-websocket-protocol", HeaderValue::from_static("protocol"));
+// Identity of the verified client certificate, if one was presented
+fn peer_cert(stream: &TlsStream<TcpStream>) -> Option<PeerCert> {
+    let (_, conn) = stream.get_ref();
+    conn.peer_certificates().and_then(|certs| certs.first()).and_then(|cert| PeerCert::parse(cert))
+}
+
+// WebSocket stream as a byte stream, WsStream is not Sync but is only used through &mut
+struct WsIo<S>(SyncWrapper<S>);
+
+impl<S: AsyncRead + Unpin> AsyncRead for WsIo<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(self.get_mut().0.get_mut()).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WsIo<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(self.get_mut().0.get_mut()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(self.get_mut().0.get_mut()).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(self.get_mut().0.get_mut()).poll_shutdown(cx)
+    }
+}
+
+// Check the WebSocket upgrade request and pick the subprotocol
+#[allow(clippy::result_large_err)]
+fn ws_callback(
+    config: &ListenerConfig,
+    request: &Request,
+    mut response: Response,
+) -> Result<Response, ErrorResponse> {
+    let reject = |status, msg: String| {
+        let mut response = ErrorResponse::new(Some(msg));
+        *response.status_mut() = status;
+        response
+    };
+
+    let path = request.uri().path();
+    if path != config.ws_path {
+        return Err(reject(StatusCode::NOT_FOUND, format!("path {} not found", path)));
+    }
+
+    if !config.ws_allowed_origins.is_empty() {
+        let origin = request.headers().get(ORIGIN).and_then(|origin| origin.to_str().ok());
+        if !origin.is_some_and(|origin| config.ws_allowed_origins.iter().any(|o| o == origin)) {
+            return Err(reject(StatusCode::FORBIDDEN, format!("origin {:?} not allowed", origin)));
+        }
+    }
+
+    // Clients offer one or more comma separated subprotocols, the first supported one is used
+    let mut offered = request
+        .headers()
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .peekable();
+    if offered.peek().is_none() {
+        return Ok(response);
+    }
+    let Some(protocol) = offered.find(|p| config.ws_subprotocols.iter().any(|s| s == p)) else {
+        let msg =
+            format!("no supported subprotocol offered, expected {:?}", config.ws_subprotocols);
+        return Err(reject(StatusCode::BAD_REQUEST, msg));
+    };
+    let protocol = HeaderValue::from_str(protocol)
+        .map_err(|e| reject(StatusCode::BAD_REQUEST, e.to_string()))?;
+    response.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, protocol);
     Ok(response)
 }
 
 pub struct MqttServer {
     ctx: Context,
     listeners: Vec<Listener>,
+    unix_listeners: Vec<Listener<UnixListener>>,
+}
+
+// Bind a unix socket file, replacing one left behind by a previous run but not one
+// a running server still accepts on
+fn bind_unix(config: &ListenerConfig) -> Result<UnixListener, Error> {
+    let path = config.path.as_ref().ok_or(anyhow!("{} path is not set", config.name))?;
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(anyhow!("{} {} is in use by a running server", config.name, path).into());
+        }
+        fs::remove_file(path)?;
+    }
+
+    // The socket is created with the configured mode, never reachable with looser permissions
+    let restore = config.mode.map(|mode| umask(Mode::from_bits_truncate(!mode & 0o777)));
+    let listener = UnixListener::bind(path);
+    if let Some(mask) = restore {
+        umask(mask);
+    }
+    listener.map_err(|e| anyhow!("{} bind {}: {}", config.name, path, e).into())
 }
 
 impl MqttServer {
@@ -198,13 +563,49 @@ impl MqttServer {
 
         let cfg = ctx.config().await;
         let mut listeners = Vec::new();
+        let mut unix_listeners = Vec::new();
+
+        for (protocol, named) in cfg.listeners {
+            for config in named.into_values() {
+                if protocol == Protocol::Unix {
+                    let listener = bind_unix(&config)?;
+                    let path = config.path.clone().unwrap_or_default();
+                    let connections =
+                        ctx.connections().register(&config.name, path, config.max_connections);
+                    let config = Arc::new(config);
+                    let protocol = protocol.clone();
+                    unix_listeners.push(Listener { protocol, listener, config, connections });
+                    continue;
+                }
 
-        for (protocol, config) in cfg.listeners {
-            let listener = TcpListener::bind(config.addr).await?;
-            listeners.push(Listener { protocol, listener, config: Arc::new(config) });
+                let addr = config.addr.ok_or(anyhow!("{} addr is not set", config.name))?;
+                let listener = socket::bind(addr, &config.socket)
+                    .map_err(|e| anyhow!("{} bind {}: {}", config.name, addr, e))?;
+                let connections = ctx.connections().register(
+                    &config.name,
+                    addr.to_string(),
+                    config.max_connections,
+                );
+                let config = Arc::new(config);
+                listeners.push(Listener {
+                    protocol: protocol.clone(),
+                    listener,
+                    config,
+                    connections,
+                });
+            }
         }
 
-        let server = Self { ctx, listeners };
+        // $SYS publisher
+        tokio::spawn(sys::run(ctx.clone()));
+
+        // Delayed publish scheduler
+        tokio::spawn(delayed::run(ctx.clone()));
+
+        // Built-in user file watcher
+        tokio::spawn(users::run(ctx.clone()));
+
+        let server = Self { ctx, listeners, unix_listeners };
         server.run().await;
 
         info!("MqttServer shutdown");
@@ -212,7 +613,15 @@ impl MqttServer {
     }
 
     async fn run(self) {
-        join_all(self.listeners.into_iter().map(|listener| {
+        let unix = self.unix_listeners.into_iter().map(|listener| {
+            let ctx = self.ctx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = listener.unix(ctx).await {
+                    error!("{}", e);
+                }
+            })
+        });
+        let inet = self.listeners.into_iter().map(|listener| {
             let ctx = self.ctx.clone();
             tokio::spawn(async move {
                 let result = match listener.protocol {
@@ -220,12 +629,276 @@ impl MqttServer {
                     Protocol::Tls => listener.tls(ctx).await,
                     Protocol::Ws => listener.ws(ctx).await,
                     Protocol::Wss => listener.wss(ctx).await,
+                    Protocol::Unix => unreachable!("unix listeners are bound separately"),
                 };
                 if let Err(e) = result {
                     error!("{}", e);
                 }
             })
-        }))
-        .await;
+        });
+        join_all(unix.chain(inet)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ReasonCode;
+    use async_tungstenite::tokio::client_async;
+    use async_tungstenite::tungstenite::client::IntoClientRequest;
+    use async_tungstenite::tungstenite::Message as WsMessage;
+    use futures::StreamExt;
+    use rcgen::{CertificateParams, KeyPair};
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::crypto::aws_lc_rs;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::TlsConnector;
+
+    // MQTT 3.1.1 CONNECT with a clean session and a two character client id, and its CONNACK
+    fn connect_packet(client_id: &[u8; 2]) -> Vec<u8> {
+        let mut packet = vec![0x10, 0x0E, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00];
+        packet.extend([0x3C, 0x00, 0x02, client_id[0], client_id[1]]);
+        packet
+    }
+    const CONNACK: [u8; 4] = [0x20, 0x02, 0x00, 0x00];
+
+    fn listener_config(protocol: &str, config: serde_json::Value) -> ListenerConfig {
+        let mut config: ListenerConfig = serde_json::from_value(config).unwrap();
+        config.name = format!("{}:test", protocol);
+        config
+    }
+
+    // Run a single listener on a free localhost port
+    async fn listen(protocol: Protocol, config: ListenerConfig) -> (SocketAddr, Context) {
+        let ctx = Context::new().await;
+        let listener = socket::bind("127.0.0.1:0".parse().unwrap(), &config.socket).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = ctx.connections().register(&config.name, addr.to_string(), 0);
+        let listener = Listener { protocol, listener, config: Arc::new(config), connections };
+        let server =
+            MqttServer { ctx: ctx.clone(), listeners: vec![listener], unix_listeners: vec![] };
+        tokio::spawn(server.run());
+        (addr, ctx)
+    }
+
+    #[tokio::test]
+    async fn test_ws() {
+        let (addr, ctx) = listen(Protocol::Ws, listener_config("ws", json!({}))).await;
+
+        let mut request = format!("ws://{}/mqtt", addr).into_client_request().unwrap();
+        request.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("mqtt"));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut ws, response) = client_async(request, stream).await.unwrap();
+        assert_eq!(response.headers()[SEC_WEBSOCKET_PROTOCOL], "mqtt");
+
+        // Packets are a byte stream over the frames, one may be split across several
+        let connect = connect_packet(b"c1");
+        ws.send(WsMessage::binary(connect[..5].to_vec())).await.unwrap();
+        ws.send(WsMessage::binary(connect[5..].to_vec())).await.unwrap();
+        let reply = ws.next().await.unwrap().unwrap();
+        assert_eq!(reply.into_data().as_ref(), CONNACK);
+
+        ctx.shutdown();
+    }
+
+    #[test]
+    fn test_ws_callback() {
+        let config = listener_config(
+            "ws",
+            json!({"ws_path": "/ws", "ws_allowed_origins": ["https://app.example.com"]}),
+        );
+        let check = |path: &str, origin: Option<&str>, protocols: &[&str]| {
+            let mut request = Request::builder().uri(path);
+            if let Some(origin) = origin {
+                request = request.header(ORIGIN, origin);
+            }
+            for protocol in protocols {
+                request = request.header(SEC_WEBSOCKET_PROTOCOL, *protocol);
+            }
+            ws_callback(&config, &request.body(()).unwrap(), Response::new(()))
+                .map(|response| response.headers().get(SEC_WEBSOCKET_PROTOCOL).cloned())
+                .map_err(|response| response.status())
+        };
+        let origin = Some("https://app.example.com");
+
+        assert_eq!(check("/ws", origin, &[]), Ok(None));
+        assert_eq!(check("/mqtt", origin, &[]), Err(StatusCode::NOT_FOUND));
+        assert_eq!(check("/ws", None, &[]), Err(StatusCode::FORBIDDEN));
+        assert_eq!(check("/ws", Some("https://evil.example.com"), &[]), Err(StatusCode::FORBIDDEN));
+
+        // The first supported subprotocol the client offers is picked
+        let picked = check("/ws", origin, &["foo, mqttv3.1.1", "mqtt"]);
+        assert_eq!(picked, Ok(Some(HeaderValue::from_static("mqttv3.1.1"))));
+        assert_eq!(check("/ws", origin, &["foo", "bar"]), Err(StatusCode::BAD_REQUEST));
+    }
+
+    // Self-signed localhost certificate and key written as PEM files
+    fn write_cert(cert_file: &str, key_file: &str) -> CertificateDer<'static> {
+        let key_pair = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".into()])
+            .unwrap()
+            .self_signed(&key_pair)
+            .unwrap();
+        fs::write(cert_file, cert.pem()).unwrap();
+        fs::write(key_file, key_pair.serialize_pem()).unwrap();
+        cert.der().clone()
+    }
+
+    #[tokio::test]
+    async fn test_tls_reload() {
+        let dir = std::env::temp_dir().join(format!("iotmq-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let (cert_file, key_file) = (path("cert.pem"), path("key.pem"));
+        let (next_cert_file, next_key_file) = (path("next-cert.pem"), path("next-key.pem"));
+        let first = write_cert(&cert_file, &key_file);
+        let config = listener_config("tls", json!({"cert": cert_file, "key": key_file}));
+        let (addr, ctx) = listen(Protocol::Tls, config).await;
+
+        let second = write_cert(&next_cert_file, &next_key_file);
+        let mut roots = RootCertStore::empty();
+        roots.add(first.clone()).unwrap();
+        roots.add(second.clone()).unwrap();
+        let client = ClientConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = &TlsConnector::from(Arc::new(client));
+        let connect = |client_id| async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(name, stream).await.unwrap();
+            stream.write_all(&connect_packet(client_id)).await.unwrap();
+            let mut connack = [0; 4];
+            stream.read_exact(&mut connack).await.unwrap();
+            assert_eq!(connack, CONNACK);
+            stream
+        };
+        // Certificate the server presented
+        let served = |stream: &tokio_rustls::client::TlsStream<TcpStream>| {
+            stream.get_ref().1.peer_certificates().unwrap()[0].clone()
+        };
+
+        let mut established = connect(b"c1").await;
+        assert_eq!(served(&established), first);
+
+        // New connections get the new certificate once the files are replaced and reloaded
+        fs::rename(next_cert_file, &cert_file).unwrap();
+        fs::rename(next_key_file, &key_file).unwrap();
+        ctx.reload();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(served(&connect(b"c2").await), second);
+
+        // The connection made before keeps working
+        established.write_all(&[0xC0, 0x00]).await.unwrap();
+        let mut pingresp = [0; 2];
+        established.read_exact(&mut pingresp).await.unwrap();
+        assert_eq!(pingresp, [0xD0, 0x00]);
+
+        ctx.shutdown();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        // The server closes the connection after the timeout but not before
+        async fn closed(mut stream: TcpStream, sent: Instant) {
+            let mut buf = [0; 64];
+            let read = timeout(Duration::from_secs(3), stream.read(&mut buf)).await;
+            assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))), "connection still open");
+            assert!(sent.elapsed() >= Duration::from_millis(900));
+        }
+
+        // A handshake or CONNECT that is never finished
+        let partial: [(Protocol, &[u8]); 2] = [
+            (Protocol::Tcp, &[0x10, 0x0E, 0x00]),
+            (Protocol::Ws, b"GET /mqtt HTTP/1.1\r\nHost: localhost\r\n"),
+        ];
+        for (protocol, partial) in partial {
+            let config = listener_config("test", json!({"connect_timeout": 1}));
+            let (addr, ctx) = listen(protocol, config).await;
+
+            let silent = TcpStream::connect(addr).await.unwrap();
+            let mut slow = TcpStream::connect(addr).await.unwrap();
+            slow.write_all(partial).await.unwrap();
+            let sent = Instant::now();
+            closed(silent, sent).await;
+            closed(slow, sent).await;
+
+            ctx.shutdown();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enhanced_auth_timeout() {
+        let config = listener_config("tcp", json!({"connect_timeout": 1}));
+        let (addr, ctx) = listen(Protocol::Tcp, config).await;
+
+        // MQTT 5 CONNECT carrying the SCRAM client-first message
+        let method = b"SCRAM-SHA-256";
+        let data = b"n,,n=alice,r=rOprNGfwEbeRWgbNEkqO";
+        let mut properties = vec![0x15, 0x00, method.len() as u8];
+        properties.extend(method);
+        properties.extend([0x16, 0x00, data.len() as u8]);
+        properties.extend(data);
+        let mut body = vec![0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0x02, 0x00, 0x3C];
+        body.push(properties.len() as u8);
+        body.extend(properties);
+        body.extend([0x00, 0x02, b'c', b'1']);
+        let mut packet = vec![0x10, body.len() as u8];
+        packet.extend(body);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&packet).await.unwrap();
+        let sent = Instant::now();
+
+        // The server-first challenge arrives, the client never answers it
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], 0xF0);
+        let mut challenge = vec![0; header[1] as usize];
+        stream.read_exact(&mut challenge).await.unwrap();
+
+        let mut buf = [0; 64];
+        let read = timeout(Duration::from_secs(3), stream.read(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))), "connection still open");
+        assert!(sent.elapsed() >= Duration::from_millis(900));
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_flapping_unauthenticated() {
+        let (addr, ctx) = listen(Protocol::Tcp, listener_config("tcp", json!({}))).await;
+        let max_count = ctx.config().await.flapping.max_count;
+
+        // MQTT 5 CONNECT with an authentication method the server does not support
+        let method = b"UNKNOWN";
+        let mut body = vec![0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0x02, 0x00, 0x3C];
+        body.extend([method.len() as u8 + 3, 0x15, 0x00, method.len() as u8]);
+        body.extend(method);
+        body.extend([0x00, 0x02, b'c', b'1']);
+        let mut packet = vec![0x10, body.len() as u8];
+        packet.extend(body);
+
+        // Failed authentications do not count towards the client id being banned
+        for _ in 0..=max_count {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(&packet).await.unwrap();
+            let mut connack = [0; 4];
+            stream.read_exact(&mut connack).await.unwrap();
+            assert_eq!(connack[3], ReasonCode::BadAuthenticationMethod as u8);
+        }
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&connect_packet(b"c1")).await.unwrap();
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack).await.unwrap();
+        assert_eq!(connack, CONNACK);
+
+        ctx.shutdown();
     }
 }
@@ -1 +1,35 @@
-mod auth_http;
+pub mod auth_http;
+
+use crate::config::ConfigError;
+use auth_http::AuthHttp;
+use config::File;
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+
+// Loaded plugins, None when disabled or not configured
+pub struct Plugins {
+    pub auth_http: Option<AuthHttp>,
+}
+
+impl Plugins {
+    pub fn load() -> Result<Self, ConfigError> {
+        Ok(Self { auth_http: AuthHttp::new(load("auth_http")?)? })
+    }
+}
+
+// Load a plugin configuration, looked up next to the main config file when -c is given
+fn load<T: DeserializeOwned>(name: &str) -> Result<T, ConfigError> {
+    let mut builder = config::Config::builder();
+
+    if let Ok(config) = std::env::var("IOTMQ__CONFIG") {
+        let mut path = PathBuf::from(config);
+        path.set_file_name(name);
+        builder = builder.add_source(File::with_name(&path.to_string_lossy()).required(false));
+    } else {
+        builder = builder
+            .add_source(File::with_name(&format!("/etc/iotmq/{}", name)).required(false))
+            .add_source(File::with_name(&format!("config/{}", name)).required(false));
+    }
+
+    builder.build()?.try_deserialize().map_err(|e| e.into())
+}
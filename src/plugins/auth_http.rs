@@ -1 +1,303 @@
+use crate::auth::{AuthResult, Authenticator, ClientInfo, Grant, TopicRule};
+use crate::config::ConfigError;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use ring::digest;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, warn};
 
+// HTTP request method
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Get,
+    Post,
+}
+
+// HTTP authentication configuration, config/auth_http.toml
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct AuthHttpConfig {
+    pub enable: bool,
+    pub method: Method,
+    pub url: String,
+    pub headers: HashMap<String, String>,
+    // Request body for post, query parameters for get. Values are templates supporting
+    // ${clientid}, ${username}, ${password}, ${peerhost}, ${proto_ver}, ${uid} and ${gid}
+    pub body: HashMap<String, String>,
+    // Request timeout in seconds
+    pub timeout: u64,
+    // Connect timeout in seconds
+    pub connect_timeout: u64,
+    // Maximum idle connections kept per host
+    pub pool_size: usize,
+    // Idle connection timeout in seconds
+    pub pool_idle_timeout: u64,
+    // Seconds an allow or deny result is cached, 0 disables caching
+    pub cache_ttl: u64,
+    // Maximum number of cached results
+    pub cache_size: usize,
+}
+
+impl Default for AuthHttpConfig {
+    fn default() -> Self {
+        let body = [
+            ("clientid", "${clientid}"),
+            ("username", "${username}"),
+            ("password", "${password}"),
+            ("peerhost", "${peerhost}"),
+            ("proto_ver", "${proto_ver}"),
+        ];
+        Self {
+            enable: false,
+            method: Method::Post,
+            url: "http://127.0.0.1:8991/mqtt/auth".to_string(),
+            headers: HashMap::new(),
+            body: body.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            timeout: 5,
+            connect_timeout: 3,
+            pool_size: 8,
+            pool_idle_timeout: 90,
+            cache_ttl: 60,
+            cache_size: 10000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Allow,
+    Deny,
+    Ignore,
+}
+
+// Response body of the authentication service
+#[derive(Debug, Deserialize)]
+struct Response {
+    result: Outcome,
+    #[serde(default)]
+    is_superuser: bool,
+    #[serde(default)]
+    acl: Vec<TopicRule>,
+}
+
+// Authenticate clients against an HTTP service
+pub struct AuthHttp {
+    cfg: AuthHttpConfig,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<Vec<u8>, (Instant, AuthResult)>>,
+}
+
+impl AuthHttp {
+    pub fn new(cfg: AuthHttpConfig) -> Result<Option<Self>, ConfigError> {
+        if !cfg.enable {
+            return Ok(None);
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(cfg.timeout))
+            .connect_timeout(Duration::from_secs(cfg.connect_timeout))
+            .pool_max_idle_per_host(cfg.pool_size)
+            .pool_idle_timeout(Duration::from_secs(cfg.pool_idle_timeout))
+            .build()
+            .map_err(|e| ConfigError::Invalid(format!("auth_http: {}", e)))?;
+        Ok(Some(Self { cfg, client, cache: Mutex::new(HashMap::new()) }))
+    }
+
+    async fn request(&self, info: &ClientInfo) -> Result<AuthResult, reqwest::Error> {
+        let params: HashMap<&str, String> =
+            self.cfg.body.iter().map(|(k, v)| (k.as_str(), info.fill(v))).collect();
+
+        let mut req = match self.cfg.method {
+            Method::Get => self.client.get(&self.cfg.url).query(&params),
+            Method::Post => {
+                let form = self.cfg.headers.iter().any(|(k, v)| {
+                    k.eq_ignore_ascii_case("content-type")
+                        && v.starts_with("application/x-www-form-urlencoded")
+                });
+                let req = self.client.post(&self.cfg.url);
+                if form {
+                    req.form(&params)
+                } else {
+                    req.json(&params)
+                }
+            }
+        };
+        for (k, v) in &self.cfg.headers {
+            req = req.header(k, v);
+        }
+
+        let resp = req.send().await?;
+        match resp.status() {
+            StatusCode::NO_CONTENT => Ok(AuthResult::Allow(Grant::default())),
+            StatusCode::OK => match resp.json::<Response>().await? {
+                Response { result: Outcome::Allow, is_superuser, acl } => {
+                    Ok(AuthResult::Allow(Grant { is_superuser, acl }))
+                }
+                Response { result: Outcome::Deny, .. } => Ok(AuthResult::Deny),
+                Response { result: Outcome::Ignore, .. } => Ok(AuthResult::Ignore),
+            },
+            status => {
+                debug!("auth_http returned {} for {}, ignored", status, info.client_id);
+                Ok(AuthResult::Ignore)
+            }
+        }
+    }
+
+    // Cache key over every value a request template can use, SHA-256 so that a client
+    // cannot craft credentials colliding with a cached result of another
+    fn key(info: &ClientInfo) -> Vec<u8> {
+        let peerhost = info.addr.ip().to_string();
+        let peer_cred = info.peer_cred.map(|cred| format!("{}:{}", cred.uid, cred.gid));
+        let values = [
+            Some(info.client_id.as_str()),
+            info.username.as_deref(),
+            info.password.as_deref(),
+            Some(peerhost.as_str()),
+            Some(info.proto_ver.as_str()),
+            peer_cred.as_deref(),
+        ];
+
+        // Length prefixed so that values cannot shift into their neighbours
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        for value in values {
+            match value {
+                Some(value) => {
+                    ctx.update(&[1]);
+                    ctx.update(&(value.len() as u64).to_be_bytes());
+                    ctx.update(value.as_bytes());
+                }
+                None => ctx.update(&[0]),
+            }
+        }
+        ctx.finish().as_ref().to_vec()
+    }
+}
+
+#[async_trait]
+impl Authenticator for AuthHttp {
+    async fn authenticate(&self, info: &ClientInfo) -> AuthResult {
+        let key = Self::key(info);
+        if let Some((expire, result)) = self.cache.lock().await.get(&key) {
+            if *expire > Instant::now() {
+                return result.clone();
+            }
+        }
+
+        let result = match self.request(info).await {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("auth_http request for {} failed: {}", info.client_id, e);
+                return AuthResult::Ignore;
+            }
+        };
+        debug!("auth_http result for {}: {:?}", info.client_id, result);
+
+        if self.cfg.cache_ttl > 0 && !matches!(result, AuthResult::Ignore) {
+            let mut cache = self.cache.lock().await;
+            let now = Instant::now();
+            if cache.len() >= self.cfg.cache_size {
+                cache.retain(|_, (expire, _)| *expire > now);
+            }
+            if cache.len() < self.cfg.cache_size {
+                let expire = now + Duration::from_secs(self.cfg.cache_ttl);
+                cache.insert(key, (expire, result.clone()));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::PeerCred;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn info(username: &str) -> ClientInfo {
+        ClientInfo {
+            client_id: "c1".into(),
+            username: Some(username.into()),
+            password: Some("secret".into()),
+            addr: "127.0.0.1:50000".parse().unwrap(),
+            proto_ver: "5.0".into(),
+            peer_cred: None,
+            identified: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = Router::new().route(
+            "/auth",
+            post(move |Json(body): Json<Value>| async move {
+                counter.fetch_add(1, Ordering::Relaxed);
+                assert_eq!(body["clientid"], "c1");
+                assert_eq!(body["peerhost"], "127.0.0.1");
+                match body["username"].as_str().unwrap() {
+                    "admin" => Json(json!({
+                        "result": "allow",
+                        "is_superuser": true,
+                        "acl": [{ "permission": "deny", "action": "publish", "topic": "x/#" }]
+                    })),
+                    "bad" => Json(json!({ "result": "deny" })),
+                    _ => Json(json!({ "result": "ignore" })),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let cfg = AuthHttpConfig {
+            enable: true,
+            url: format!("http://{}/auth", addr),
+            ..Default::default()
+        };
+        let auth = AuthHttp::new(cfg).unwrap().unwrap();
+
+        match auth.authenticate(&info("admin")).await {
+            AuthResult::Allow(grant) => {
+                assert!(grant.is_superuser);
+                assert_eq!(grant.acl[0].topic, "x/#");
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(matches!(auth.authenticate(&info("bad")).await, AuthResult::Deny));
+        assert!(matches!(auth.authenticate(&info("guest")).await, AuthResult::Ignore));
+
+        // Allow and deny are cached, ignore is not
+        auth.authenticate(&info("admin")).await;
+        auth.authenticate(&info("bad")).await;
+        auth.authenticate(&info("guest")).await;
+        assert_eq!(hits.load(Ordering::Relaxed), 4);
+
+        // Unix socket peers are cached apart from inet clients with the same credentials
+        let mut peer = info("admin");
+        peer.peer_cred = Some(PeerCred { uid: 1000, gid: 1000 });
+        auth.authenticate(&peer).await;
+        assert_eq!(hits.load(Ordering::Relaxed), 5);
+        let mut shifted = info("adminsecret");
+        shifted.password = Some(String::new());
+        assert_ne!(AuthHttp::key(&info("admin")), AuthHttp::key(&shifted));
+
+        // Unreachable service is ignored
+        let cfg = AuthHttpConfig {
+            enable: true,
+            url: "http://127.0.0.1:1/auth".into(),
+            ..Default::default()
+        };
+        let auth = AuthHttp::new(cfg).unwrap().unwrap();
+        assert!(matches!(auth.authenticate(&info("admin")).await, AuthResult::Ignore));
+    }
+}
@@ -23,10 +23,10 @@ impl Decoder for Codec {
 impl Encoder<Packet> for Codec {
     type Error = Error;
     fn encode(&mut self, item: Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        Ok(match self {
-            Codec::V3(codec) => codec.encode(item, dst)?,
-            Codec::V5(codec) => codec.encode(item, dst)?,
-            _ => (),
-        })
+        match self {
+            Codec::V3(codec) => codec.encode(item, dst),
+            Codec::V5(codec) => codec.encode(item, dst),
+            Codec::Version(_) => Ok(()),
+        }
     }
 }
@@ -16,11 +16,15 @@ pub enum Error {
     #[error("Malformed packet")]
     MalformedPacket,
     #[error("Invalid protocol: {0}")]
-    ProtocolError(String),
+    Protocol(String),
     #[error("Unsupported protocol version: {0}")]
     UnsupportedProtocolVersion(String),
     #[error("Client disconnect: {0}")]
     Disconnect(String),
+    #[error("No CONNECT from {0} in time")]
+    ConnectTimeout(String),
+    #[error("Packet size {0} exceeds the maximum of {1}")]
+    PacketTooLarge(usize, u32),
     #[error("Length too big")]
     LenTooLong,
     #[error("Anyhow: {0}")]
@@ -28,19 +32,15 @@ pub enum Error {
 }
 
 #[repr(u8)]
-#[derive(Debug, TryFromPrimitive)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive)]
 pub enum QoS {
+    #[default]
     AtMostOnce = 0,
     AtLeastOnce,
     ExactlyOnce,
 }
 
-impl Default for QoS {
-    fn default() -> Self {
-        Self::AtMostOnce
-    }
-}
-
 #[repr(u8)]
 #[derive(Debug, TryFromPrimitive)]
 pub enum PacketType {
@@ -68,19 +68,19 @@ pub enum Packet {
     Version(Version),
     Connect(Connect),
     ConnAck(ConnAck),
-    // Publish(Publish),
-    // PubAck(PubAck),
-    // PubRec(PubRec),
-    // PubRel(PubRel),
-    // PubComp(PubComp),
-    // Subscribe(Subscribe),
-    // SubAck(SubAck),
-    // Unsubscribe(Unsubscribe),
-    // UnsubAck(UnsubAck),
-    // PingReq,
-    // PingResp,
-    // Disconnect(Disconnect),
-    // Auth(Auth)
+    Publish(Publish),
+    PubAck(PubAck),
+    PubRec(PubRec),
+    PubRel(PubRel),
+    PubComp(PubComp),
+    Subscribe(Subscribe),
+    SubAck(SubAck),
+    Unsubscribe(Unsubscribe),
+    UnsubAck(UnsubAck),
+    PingReq,
+    PingResp,
+    Disconnect(Disconnect),
+    Auth(Auth),
 }
 
 // CONNECT Packet
@@ -106,6 +106,7 @@ pub struct Connect {
 
 #[derive(Debug, TryFromPrimitive)]
 #[repr(u8)]
+#[allow(clippy::enum_variant_names)]
 pub enum Property {
     PayloadFormatIndicator = 0x01,
     MessageExpiryInterval = 0x02,
@@ -136,6 +137,56 @@ pub enum Property {
     SharedSubAvailable = 0x2A,
 }
 
+// Reason Code, the full MQTT 5 table although the broker does not send every code
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReasonCode {
+    Success = 0x00,
+    GrantedQoS1 = 0x01,
+    GrantedQoS2 = 0x02,
+    NoMatchingSubscribers = 0x10,
+    NoSubscriptionExisted = 0x11,
+    ContinueAuthentication = 0x18,
+    ReAuthenticate = 0x19,
+    UnspecifiedError = 0x80,
+    MalformedPacket = 0x81,
+    ProtocolError = 0x82,
+    ImplementationSpecificError = 0x83,
+    UnsupportedProtocolVersion = 0x84,
+    ClientIdentifierNotValid = 0x85,
+    BadUserNameOrPassword = 0x86,
+    NotAuthorized = 0x87,
+    ServerUnavailable = 0x88,
+    ServerBusy = 0x89,
+    Banned = 0x8A,
+    ServerShuttingDown = 0x8B,
+    BadAuthenticationMethod = 0x8C,
+    KeepAliveTimeout = 0x8D,
+    SessionTakenOver = 0x8E,
+    TopicFilterInvalid = 0x8F,
+    TopicNameInvalid = 0x90,
+    PacketIdentifierInUse = 0x91,
+    PacketIdentifierNotFound = 0x92,
+    ReceiveMaximumExceeded = 0x93,
+    TopicAliasInvalid = 0x94,
+    PacketTooLarge = 0x95,
+    MessageRateTooHigh = 0x96,
+    QuotaExceeded = 0x97,
+    AdministrativeAction = 0x98,
+    PayloadFormatInvalid = 0x99,
+    RetainNotSupported = 0x9A,
+    QoSNotSupported = 0x9B,
+    UseAnotherServer = 0x9C,
+    ServerMoved = 0x9D,
+    SharedSubscriptionsNotSupported = 0x9E,
+    ConnectionRateExceeded = 0x9F,
+    MaximumConnectTime = 0xA0,
+    SubscriptionIdentifiersNotSupported = 0xA1,
+    WildcardSubscriptionsNotSupported = 0xA2,
+}
+
+// CONNACK Packet
 #[derive(Debug, Default)]
 pub struct ConnAck {
     pub session_present: bool,
@@ -143,6 +194,85 @@ pub struct ConnAck {
     pub properties: Option<v5::ConnAckProperties>,
 }
 
+// PUBLISH Packet
+#[derive(Debug, Default, Clone)]
+pub struct Publish {
+    pub dup: bool,
+    pub qos: QoS,
+    pub retain: bool,
+    pub topic: String,
+    pub packet_id: Option<u16>,
+    pub properties: Option<v5::PublishProperties>,
+    pub payload: Bytes,
+}
+
+// PUBACK, PUBREC, PUBREL and PUBCOMP Packet
+#[derive(Debug, Default)]
+pub struct PubAck {
+    pub packet_id: u16,
+    pub reason_code: u8,
+    pub properties: Option<v5::AckProperties>,
+}
+
+pub type PubRec = PubAck;
+pub type PubRel = PubAck;
+pub type PubComp = PubAck;
+
+// SUBSCRIBE Packet
+#[derive(Debug, Default)]
+pub struct Subscribe {
+    pub packet_id: u16,
+    pub properties: Option<v5::SubscribeProperties>,
+    pub filters: Vec<(String, SubscribeOptions)>,
+}
+
+// Subscription Options
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SubscribeOptions {
+    pub qos: QoS,
+    pub no_local: bool,
+    pub retain_as_published: bool,
+    pub retain_handling: u8,
+}
+
+// SUBACK Packet
+#[derive(Debug, Default)]
+pub struct SubAck {
+    pub packet_id: u16,
+    pub properties: Option<v5::AckProperties>,
+    pub reason_codes: Vec<u8>,
+}
+
+// UNSUBSCRIBE Packet
+#[derive(Debug, Default)]
+pub struct Unsubscribe {
+    pub packet_id: u16,
+    pub properties: Option<v5::UnsubscribeProperties>,
+    pub filters: Vec<String>,
+}
+
+// UNSUBACK Packet
+#[derive(Debug, Default)]
+pub struct UnsubAck {
+    pub packet_id: u16,
+    pub properties: Option<v5::AckProperties>,
+    pub reason_codes: Vec<u8>,
+}
+
+// DISCONNECT Packet
+#[derive(Debug, Default)]
+pub struct Disconnect {
+    pub reason_code: u8,
+    pub properties: Option<v5::DisconnectProperties>,
+}
+
+// AUTH Packet
+#[derive(Debug, Default)]
+pub struct Auth {
+    pub reason_code: u8,
+    pub properties: Option<v5::AuthProperties>,
+}
+
 // Protocol level
 struct Level(u8);
 impl Level {
@@ -206,9 +336,8 @@ fn len_len(mut len: usize) -> usize {
 
 // Decode string
 fn decode_string(src: &mut Bytes) -> Result<String, Error> {
-    let len = src.get_u16() as usize;
-    let bytes = src.split_to(len);
-    let str = String::from_utf8(bytes.to_vec()).map_err(|e| Error::ProtocolError(e.to_string()))?;
+    let bytes = decode_binary(src)?;
+    let str = String::from_utf8(bytes.to_vec()).map_err(|e| Error::Protocol(e.to_string()))?;
     Ok(str)
 }
 
@@ -218,6 +347,46 @@ fn encode_string(dst: &mut BytesMut, str: &str) {
     dst.extend_from_slice(str.as_bytes());
 }
 
+// Decode binary data
+fn decode_binary(src: &mut Bytes) -> Result<Bytes, Error> {
+    if src.remaining() < 2 {
+        return Err(Error::MalformedPacket);
+    }
+    let len = src.get_u16() as usize;
+    if src.remaining() < len {
+        return Err(Error::MalformedPacket);
+    }
+    Ok(src.split_to(len))
+}
+
+// Encode binary data
+fn encode_binary(dst: &mut BytesMut, bytes: &[u8]) {
+    dst.put_u16(bytes.len() as u16);
+    dst.extend_from_slice(bytes);
+}
+
+// Check topic name, wildcards are not allowed
+pub fn valid_topic(topic: &str) -> bool {
+    !topic.is_empty() && !topic.contains(['+', '#', '\0'])
+}
+
+// Check topic filter
+pub fn valid_filter(filter: &str) -> bool {
+    if filter.is_empty() || filter.contains('\0') {
+        return false;
+    }
+    let levels: Vec<&str> = filter.split('/').collect();
+    for (i, level) in levels.iter().enumerate() {
+        match *level {
+            "#" if i != levels.len() - 1 => return false,
+            "#" | "+" => {}
+            _ if level.contains(['+', '#']) => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -251,6 +420,37 @@ mod tests {
         assert_eq!(buf[..], [0xFF, 0xFF, 0xFF, 0x7F]);
     }
 
+    #[test]
+    fn test_valid_filter() {
+        assert!(valid_filter("a/b/c"));
+        assert!(valid_filter("a/+/c"));
+        assert!(valid_filter("a/#"));
+        assert!(valid_filter("#"));
+        assert!(valid_filter("+/+"));
+        assert!(!valid_filter(""));
+        assert!(!valid_filter("a/#/c"));
+        assert!(!valid_filter("a/b+"));
+        assert!(!valid_filter("a/#b"));
+
+        assert!(valid_topic("a/b/c"));
+        assert!(!valid_topic("a/+/c"));
+        assert!(!valid_topic("a/#"));
+        assert!(!valid_topic(""));
+    }
+
+    #[test]
+    fn test_decode_string() {
+        let mut src = Bytes::from_static(&[0x00, 0x02, b'a', b'b', b'c']);
+        assert_eq!(decode_string(&mut src).unwrap(), "ab");
+        assert_eq!(src[..], [b'c']);
+
+        // Truncated length and a length past the end of the packet
+        for src in [&[][..], &[0x00], &[0x00, 0x05, b'a']] {
+            let mut src = Bytes::copy_from_slice(src);
+            assert!(matches!(decode_string(&mut src), Err(Error::MalformedPacket)));
+        }
+    }
+
     #[test]
     fn test_decode_len() {
         let src = &[];
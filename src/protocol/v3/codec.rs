@@ -1,10 +1,13 @@
-use super::connect;
-use crate::protocol::{decode_len, Error, Packet, PacketType};
-use bytes::Buf;
+use super::{connack, connect, puback, publish, suback, subscribe, unsuback, unsubscribe};
+use crate::protocol::{decode_len, Disconnect, Error, Packet, PacketType};
+use bytes::{Buf, BufMut};
 use tokio_util::bytes::BytesMut;
 use tokio_util::codec::{Decoder, Encoder};
 
-pub struct Codec;
+pub struct Codec {
+    // Larger packets are rejected before they are buffered
+    pub max_packet_size: u32,
+}
 
 impl Decoder for Codec {
     type Item = (Packet, u32);
@@ -18,9 +21,13 @@ impl Decoder for Codec {
         // Decode remaining length
         let bytes = src.as_ref();
         let packet_type = bytes[0] >> 4;
+        let flags = bytes[0] & 0x0F;
         let (bytes, packet_size) = match decode_len(&bytes[1..])? {
             Some((len, len_len)) => {
                 let packet_size = 1 + len_len + len;
+                if packet_size > self.max_packet_size as usize {
+                    return Err(Error::PacketTooLarge(packet_size, self.max_packet_size));
+                }
                 if src.len() < packet_size {
                     src.reserve(packet_size);
                     return Ok(None);
@@ -36,7 +43,16 @@ impl Decoder for Codec {
         let packet_type = PacketType::try_from(packet_type).map_err(|_| Error::MalformedPacket)?;
         let packet = match packet_type {
             PacketType::Connect => Packet::Connect(connect::decode(bytes)?),
-            _ => unreachable!(),
+            PacketType::Publish => Packet::Publish(publish::decode(flags, bytes)?),
+            PacketType::PubAck => Packet::PubAck(puback::decode(bytes)?),
+            PacketType::PubRec => Packet::PubRec(puback::decode(bytes)?),
+            PacketType::PubRel => Packet::PubRel(puback::decode(bytes)?),
+            PacketType::PubComp => Packet::PubComp(puback::decode(bytes)?),
+            PacketType::Subscribe => Packet::Subscribe(subscribe::decode(bytes)?),
+            PacketType::Unsubscribe => Packet::Unsubscribe(unsubscribe::decode(bytes)?),
+            PacketType::PingReq => Packet::PingReq,
+            PacketType::Disconnect => Packet::Disconnect(Disconnect::default()),
+            _ => return Err(Error::Protocol(format!("[packet type: {:?}]", packet_type))),
         };
 
         Ok(Some((packet, packet_size)))
@@ -46,7 +62,19 @@ impl Decoder for Codec {
 impl Encoder<Packet> for Codec {
     type Error = Error;
     fn encode(&mut self, item: Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        println!("{item:?}");
+        match item {
+            Packet::ConnAck(connack) => connack::encode(connack, dst)?,
+            Packet::Publish(publish) => publish::encode(publish, dst)?,
+            Packet::PubAck(puback) => puback::encode(PacketType::PubAck, puback, dst),
+            Packet::PubRec(pubrec) => puback::encode(PacketType::PubRec, pubrec, dst),
+            Packet::PubRel(pubrel) => puback::encode(PacketType::PubRel, pubrel, dst),
+            Packet::PubComp(pubcomp) => puback::encode(PacketType::PubComp, pubcomp, dst),
+            Packet::SubAck(suback) => suback::encode(suback, dst)?,
+            Packet::UnsubAck(unsuback) => unsuback::encode(unsuback, dst),
+            Packet::PingResp => dst.put_slice(&[(PacketType::PingResp as u8) << 4, 0]),
+            // There is no server side DISCONNECT in MQTT 3.x, the connection is simply closed
+            _ => (),
+        }
         Ok(())
     }
 }
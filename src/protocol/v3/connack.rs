@@ -0,0 +1,27 @@
+use crate::protocol::{ConnAck, Error, PacketType, ReasonCode};
+use bytes::{BufMut, BytesMut};
+
+pub fn encode(packet: ConnAck, dst: &mut BytesMut) -> Result<(), Error> {
+    dst.put_u8((PacketType::ConnAck as u8) << 4);
+    dst.put_u8(2);
+    dst.put_u8(packet.session_present as u8);
+    dst.put_u8(return_code(packet.reason_code));
+    Ok(())
+}
+
+// Map MQTT 5 reason code to MQTT 3.x connect return code
+fn return_code(reason_code: u8) -> u8 {
+    match reason_code {
+        0x00 => 0,
+        c if c == ReasonCode::UnsupportedProtocolVersion as u8 => 1,
+        c if c == ReasonCode::ClientIdentifierNotValid as u8 => 2,
+        c if c == ReasonCode::BadUserNameOrPassword as u8 => 4,
+        c if c == ReasonCode::NotAuthorized as u8
+            || c == ReasonCode::Banned as u8
+            || c == ReasonCode::BadAuthenticationMethod as u8 =>
+        {
+            5
+        }
+        _ => 3,
+    }
+}
@@ -1,4 +1,4 @@
-use crate::protocol::{decode_len, decode_string, Connect, Error, Level, Property, QoS};
+use crate::protocol::{decode_string, Connect, Error, Level, QoS};
 use bytes::{Buf, Bytes};
 
 pub fn decode(mut src: Bytes) -> Result<Connect, Error> {
@@ -16,7 +16,7 @@ pub fn decode(mut src: Bytes) -> Result<Connect, Error> {
     connect.will_retain = connect_flags & 0x20 > 0;
     let qos = (connect_flags & 0x18) >> 3;
     connect.will_qos =
-        QoS::try_from(qos).map_err(|_| Error::ProtocolError(format!("[QoS: {}]", qos)))?;
+        QoS::try_from(qos).map_err(|_| Error::Protocol(format!("[QoS: {}]", qos)))?;
     connect.will_flag = connect_flags & 0x04 > 0;
     connect.clean_start = connect_flags & 0x02 > 0;
 
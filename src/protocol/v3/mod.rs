@@ -1,4 +1,11 @@
 mod codec;
+mod connack;
 mod connect;
+mod puback;
+mod publish;
+mod suback;
+mod subscribe;
+mod unsuback;
+mod unsubscribe;
 
 pub use codec::Codec;
@@ -0,0 +1,17 @@
+use crate::protocol::{Error, PacketType, PubAck};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+pub fn decode(mut src: Bytes) -> Result<PubAck, Error> {
+    if src.remaining() < 2 {
+        return Err(Error::MalformedPacket);
+    }
+    Ok(PubAck { packet_id: src.get_u16(), ..Default::default() })
+}
+
+pub fn encode(packet_type: PacketType, packet: PubAck, dst: &mut BytesMut) {
+    // PUBREL has fixed header flags 0b0010
+    let flags = if matches!(packet_type, PacketType::PubRel) { 0x02 } else { 0x00 };
+    dst.put_u8((packet_type as u8) << 4 | flags);
+    dst.put_u8(2);
+    dst.put_u16(packet.packet_id);
+}
@@ -0,0 +1,46 @@
+use crate::protocol::{decode_string, encode_len, encode_string, Error, PacketType, Publish, QoS};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+pub fn decode(flags: u8, mut src: Bytes) -> Result<Publish, Error> {
+    let mut publish = Publish { ..Default::default() };
+
+    // Fixed header flags
+    publish.dup = flags & 0x08 > 0;
+    let qos = (flags & 0x06) >> 1;
+    publish.qos = QoS::try_from(qos).map_err(|_| Error::Protocol(format!("[QoS: {}]", qos)))?;
+    publish.retain = flags & 0x01 > 0;
+
+    // Topic Name
+    publish.topic = decode_string(&mut src)?;
+
+    // Packet Identifier
+    if publish.qos != QoS::AtMostOnce {
+        if src.remaining() < 2 {
+            return Err(Error::MalformedPacket);
+        }
+        publish.packet_id = Some(src.get_u16());
+    }
+
+    // Payload
+    publish.payload = src;
+
+    Ok(publish)
+}
+
+pub fn encode(packet: Publish, dst: &mut BytesMut) -> Result<(), Error> {
+    let mut len = 2 + packet.topic.len() + packet.payload.len();
+    if packet.packet_id.is_some() {
+        len += 2;
+    }
+
+    let flags = (packet.dup as u8) << 3 | (packet.qos as u8) << 1 | packet.retain as u8;
+    dst.put_u8((PacketType::Publish as u8) << 4 | flags);
+    encode_len(dst, len)?;
+    encode_string(dst, &packet.topic);
+    if let Some(packet_id) = packet.packet_id {
+        dst.put_u16(packet_id);
+    }
+
+    dst.extend_from_slice(&packet.payload);
+    Ok(())
+}
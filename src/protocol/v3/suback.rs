@@ -0,0 +1,14 @@
+use crate::protocol::{encode_len, Error, PacketType, SubAck};
+use bytes::{BufMut, BytesMut};
+
+pub fn encode(packet: SubAck, dst: &mut BytesMut) -> Result<(), Error> {
+    dst.put_u8((PacketType::SubAck as u8) << 4);
+    encode_len(dst, 2 + packet.reason_codes.len())?;
+    dst.put_u16(packet.packet_id);
+
+    // MQTT 3.x only knows the granted QoS or 0x80 failure
+    for reason_code in packet.reason_codes {
+        dst.put_u8(if reason_code < 0x80 { reason_code } else { 0x80 });
+    }
+    Ok(())
+}
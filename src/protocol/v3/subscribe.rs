@@ -0,0 +1,26 @@
+use crate::protocol::{decode_string, Error, QoS, Subscribe, SubscribeOptions};
+use bytes::{Buf, Bytes};
+
+pub fn decode(mut src: Bytes) -> Result<Subscribe, Error> {
+    if src.remaining() < 2 {
+        return Err(Error::MalformedPacket);
+    }
+    let mut subscribe = Subscribe { packet_id: src.get_u16(), ..Default::default() };
+
+    // Topic Filters
+    while src.has_remaining() {
+        let filter = decode_string(&mut src)?;
+        if !src.has_remaining() {
+            return Err(Error::MalformedPacket);
+        }
+        let qos = src.get_u8();
+        let qos = QoS::try_from(qos).map_err(|_| Error::Protocol(format!("[QoS: {}]", qos)))?;
+        subscribe.filters.push((filter, SubscribeOptions { qos, ..Default::default() }));
+    }
+
+    if subscribe.filters.is_empty() {
+        return Err(Error::Protocol("SUBSCRIBE without topic filters".into()));
+    }
+
+    Ok(subscribe)
+}
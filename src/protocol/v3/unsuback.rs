@@ -0,0 +1,8 @@
+use crate::protocol::{PacketType, UnsubAck};
+use bytes::{BufMut, BytesMut};
+
+pub fn encode(packet: UnsubAck, dst: &mut BytesMut) {
+    dst.put_u8((PacketType::UnsubAck as u8) << 4);
+    dst.put_u8(2);
+    dst.put_u16(packet.packet_id);
+}
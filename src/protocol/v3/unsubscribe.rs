@@ -0,0 +1,20 @@
+use crate::protocol::{decode_string, Error, Unsubscribe};
+use bytes::{Buf, Bytes};
+
+pub fn decode(mut src: Bytes) -> Result<Unsubscribe, Error> {
+    if src.remaining() < 2 {
+        return Err(Error::MalformedPacket);
+    }
+    let mut unsubscribe = Unsubscribe { packet_id: src.get_u16(), ..Default::default() };
+
+    // Topic Filters
+    while src.has_remaining() {
+        unsubscribe.filters.push(decode_string(&mut src)?);
+    }
+
+    if unsubscribe.filters.is_empty() {
+        return Err(Error::Protocol("UNSUBSCRIBE without topic filters".into()));
+    }
+
+    Ok(unsubscribe)
+}
@@ -0,0 +1,138 @@
+use super::properties;
+use crate::protocol::{
+    decode_string, encode_len, encode_string, len_len, Auth, Error, PacketType, Property,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+pub fn decode(mut src: Bytes) -> Result<Auth, Error> {
+    let mut auth = Auth { ..Default::default() };
+
+    // The Reason Code and Property Length can be omitted if the Reason Code is 0x00
+    if src.has_remaining() {
+        auth.reason_code = src.get_u8();
+    }
+    if src.has_remaining() {
+        auth.properties = AuthProperties::decode(&mut src)?;
+    }
+
+    Ok(auth)
+}
+
+pub fn encode(packet: Auth, dst: &mut BytesMut) -> Result<(), Error> {
+    let prop_len = packet.properties.as_ref().map_or(0, |prop| prop.len());
+
+    dst.put_u8((PacketType::Auth as u8) << 4);
+    if packet.reason_code == 0 && prop_len == 0 {
+        encode_len(dst, 0)?;
+        return Ok(());
+    }
+
+    let len = 1 + len_len(prop_len) + prop_len;
+    encode_len(dst, len)?;
+    dst.put_u8(packet.reason_code);
+
+    encode_len(dst, prop_len)?;
+    if let Some(prop) = packet.properties {
+        prop.encode(dst);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct AuthProperties {
+    pub auth_method: Option<String>,
+    pub auth_data: Option<Vec<u8>>,
+    pub reason_string: Option<String>,
+    pub user_property: Vec<(String, String)>,
+}
+
+impl AuthProperties {
+    fn decode(src: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut src = match properties(src)? {
+            Some(src) => src,
+            None => return Ok(None),
+        };
+        let mut prop = Self::default();
+
+        while src.has_remaining() {
+            let id = src.get_u8();
+            let property = Property::try_from(id).map_err(|_| Error::MalformedPacket)?;
+            match property {
+                Property::AuthMethod => {
+                    prop.auth_method = Some(decode_string(&mut src)?);
+                }
+
+                Property::AuthData => {
+                    if src.remaining() < 2 {
+                        return Err(Error::MalformedPacket);
+                    }
+                    let len = src.get_u16() as usize;
+                    if src.remaining() < len {
+                        return Err(Error::MalformedPacket);
+                    }
+                    prop.auth_data = Some(src.split_to(len).to_vec());
+                }
+
+                Property::ReasonString => {
+                    prop.reason_string = Some(decode_string(&mut src)?);
+                }
+
+                Property::UserProperty => {
+                    let k = decode_string(&mut src)?;
+                    let v = decode_string(&mut src)?;
+                    prop.user_property.push((k, v));
+                }
+                _ => return Err(Error::MalformedPacket),
+            }
+        }
+
+        Ok(Some(prop))
+    }
+
+    pub fn encode(self, dst: &mut BytesMut) {
+        if let Some(auth_method) = self.auth_method {
+            dst.put_u8(Property::AuthMethod as u8);
+            encode_string(dst, &auth_method);
+        }
+
+        if let Some(auth_data) = self.auth_data {
+            dst.put_u8(Property::AuthData as u8);
+            dst.put_u16(auth_data.len() as u16);
+            dst.extend_from_slice(&auth_data);
+        }
+
+        if let Some(reason_string) = self.reason_string {
+            dst.put_u8(Property::ReasonString as u8);
+            encode_string(dst, &reason_string);
+        }
+
+        for (k, v) in self.user_property.iter() {
+            dst.put_u8(Property::UserProperty as u8);
+            encode_string(dst, k);
+            encode_string(dst, v);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+
+        if let Some(ref auth_method) = self.auth_method {
+            len += 1 + 2 + auth_method.len();
+        }
+
+        if let Some(ref auth_data) = self.auth_data {
+            len += 1 + 2 + auth_data.len();
+        }
+
+        if let Some(ref reason_string) = self.reason_string {
+            len += 1 + 2 + reason_string.len();
+        }
+
+        for (k, v) in self.user_property.iter() {
+            len += 1 + 2 + k.len() + 2 + v.len();
+        }
+
+        len
+    }
+}
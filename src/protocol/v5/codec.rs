@@ -1,11 +1,16 @@
-use super::{connack, connect};
-use crate::protocol::{decode_len, Connect, Error, Packet, PacketType};
-use bytes::Buf;
+use super::{
+    auth, connack, connect, disconnect, puback, publish, suback, subscribe, unsuback, unsubscribe,
+};
+use crate::protocol::{decode_len, Error, Packet, PacketType};
+use bytes::{Buf, BufMut};
 use tokio_util::bytes::BytesMut;
 use tokio_util::codec::{Decoder, Encoder};
 
 #[derive(Debug)]
-pub struct Codec;
+pub struct Codec {
+    // Larger packets are rejected before they are buffered
+    pub max_packet_size: u32,
+}
 
 impl Decoder for Codec {
     type Item = (Packet, u32);
@@ -19,9 +24,13 @@ impl Decoder for Codec {
         // Decode remaining length
         let bytes = src.as_ref();
         let packet_type = bytes[0] >> 4;
+        let flags = bytes[0] & 0x0F;
         let (bytes, packet_size) = match decode_len(&bytes[1..])? {
             Some((len, len_len)) => {
                 let packet_size = 1 + len_len + len;
+                if packet_size > self.max_packet_size as usize {
+                    return Err(Error::PacketTooLarge(packet_size, self.max_packet_size));
+                }
                 if src.len() < packet_size {
                     src.reserve(packet_size);
                     return Ok(None);
@@ -37,7 +46,17 @@ impl Decoder for Codec {
         let packet_type = PacketType::try_from(packet_type).map_err(|_| Error::MalformedPacket)?;
         let packet = match packet_type {
             PacketType::Connect => Packet::Connect(connect::decode(bytes)?),
-            _ => unreachable!(),
+            PacketType::Publish => Packet::Publish(publish::decode(flags, bytes)?),
+            PacketType::PubAck => Packet::PubAck(puback::decode(bytes)?),
+            PacketType::PubRec => Packet::PubRec(puback::decode(bytes)?),
+            PacketType::PubRel => Packet::PubRel(puback::decode(bytes)?),
+            PacketType::PubComp => Packet::PubComp(puback::decode(bytes)?),
+            PacketType::Subscribe => Packet::Subscribe(subscribe::decode(bytes)?),
+            PacketType::Unsubscribe => Packet::Unsubscribe(unsubscribe::decode(bytes)?),
+            PacketType::PingReq => Packet::PingReq,
+            PacketType::Disconnect => Packet::Disconnect(disconnect::decode(bytes)?),
+            PacketType::Auth => Packet::Auth(auth::decode(bytes)?),
+            _ => return Err(Error::Protocol(format!("[packet type: {:?}]", packet_type))),
         };
 
         Ok(Some((packet, packet_size)))
@@ -49,8 +68,174 @@ impl Encoder<Packet> for Codec {
     fn encode(&mut self, item: Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
         match item {
             Packet::ConnAck(connack) => connack::encode(connack, dst)?,
+            Packet::Publish(publish) => publish::encode(publish, dst)?,
+            Packet::PubAck(puback) => puback::encode(PacketType::PubAck, puback, dst)?,
+            Packet::PubRec(pubrec) => puback::encode(PacketType::PubRec, pubrec, dst)?,
+            Packet::PubRel(pubrel) => puback::encode(PacketType::PubRel, pubrel, dst)?,
+            Packet::PubComp(pubcomp) => puback::encode(PacketType::PubComp, pubcomp, dst)?,
+            Packet::SubAck(suback) => suback::encode(suback, dst)?,
+            Packet::UnsubAck(unsuback) => unsuback::encode(unsuback, dst)?,
+            Packet::PingResp => dst.put_slice(&[(PacketType::PingResp as u8) << 4, 0]),
+            Packet::Disconnect(disconnect) => disconnect::encode(disconnect, dst)?,
+            Packet::Auth(auth) => auth::encode(auth, dst)?,
             _ => (),
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::v5::{
+        AckProperties, AuthProperties, ConnAckProperties, DisconnectProperties, PublishProperties,
+    };
+    use crate::protocol::{
+        Auth, ConnAck, Disconnect, PubAck, Publish, QoS, ReasonCode, SubAck, UnsubAck,
+    };
+    use bytes::Bytes;
+
+    // Encode and decode again, the packet must come back whole from exactly the written bytes
+    fn assert_round_trip(packet: Packet) {
+        let expected = format!("{:?}", packet);
+        let mut codec = Codec { max_packet_size: 1024 };
+        let mut buf = BytesMut::new();
+        codec.encode(packet, &mut buf).unwrap();
+        let written = buf.len();
+        let (packet, size) = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(size as usize, written);
+        assert!(buf.is_empty());
+        assert_eq!(format!("{:?}", packet), expected);
+    }
+
+    // Packets only the server sends, the remaining length must match what was written
+    fn framed(packet: Packet) -> BytesMut {
+        let mut buf = BytesMut::new();
+        Codec { max_packet_size: 1024 }.encode(packet, &mut buf).unwrap();
+        let (len, len_len) = decode_len(&buf[1..]).unwrap().unwrap();
+        assert_eq!(1 + len_len + len, buf.len());
+        buf
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let properties = PublishProperties {
+            payload_format_indicator: Some(1),
+            message_expiry_interval: Some(60),
+            topic_alias: Some(3),
+            response_topic: Some("a/reply".into()),
+            correlation_data: Some(Bytes::from_static(b"id")),
+            user_property: vec![("k".into(), "v".into())],
+            sub_identifiers: vec![1, 300],
+            content_type: Some("text/plain".into()),
+        };
+        let publish = Publish {
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            topic: "a/b".into(),
+            packet_id: Some(7),
+            properties: Some(properties),
+            payload: Bytes::from_static(b"hello"),
+            ..Default::default()
+        };
+        assert_round_trip(Packet::Publish(publish));
+
+        let ack = || PubAck {
+            packet_id: 9,
+            reason_code: ReasonCode::NoMatchingSubscribers as u8,
+            properties: Some(AckProperties::new("no subscribers").with("topic", "a/b")),
+        };
+        for packet in [Packet::PubAck(ack()), Packet::PubRec(ack()), Packet::PubRel(ack())] {
+            assert_round_trip(packet);
+        }
+
+        let disconnect = Disconnect {
+            reason_code: ReasonCode::ServerMoved as u8,
+            properties: Some(DisconnectProperties {
+                reason_string: Some("moved".into()),
+                server_reference: Some("other:1883".into()),
+                ..Default::default()
+            }),
+        };
+        assert_round_trip(Packet::Disconnect(disconnect));
+
+        let auth = Auth {
+            reason_code: 0x18,
+            properties: Some(AuthProperties {
+                auth_method: Some("SCRAM-SHA-256".into()),
+                auth_data: Some(b"r=nonce".to_vec()),
+                ..Default::default()
+            }),
+        };
+        assert_round_trip(Packet::Auth(auth));
+
+        // Reason code 0 without properties is sent as a bare header
+        let disconnect = Disconnect::default();
+        assert_eq!(framed(Packet::Disconnect(disconnect))[..], [0xE0, 0x00]);
+    }
+
+    #[test]
+    fn test_encode_acks() {
+        let properties = ConnAckProperties {
+            assigned_client_identifier: Some("auto-1".into()),
+            max_packet_size: Some(1024),
+            shared_sub_available: Some(0),
+            user_property: vec![("k".into(), "v".into())],
+            ..Default::default()
+        };
+        let connack =
+            ConnAck { session_present: true, reason_code: 0, properties: Some(properties) };
+        let buf = framed(Packet::ConnAck(connack));
+        assert_eq!(buf[..4], [0x20, buf.len() as u8 - 2, 0x01, 0x00]);
+
+        let suback = SubAck { packet_id: 1, properties: None, reason_codes: vec![0, 1, 0x87] };
+        assert_eq!(
+            framed(Packet::SubAck(suback))[..],
+            [0x90, 0x06, 0x00, 0x01, 0x00, 0x00, 0x01, 0x87]
+        );
+
+        let unsuback = UnsubAck {
+            packet_id: 2,
+            properties: Some(AckProperties::new("gone")),
+            reason_codes: vec![0x11],
+        };
+        let buf = framed(Packet::UnsubAck(unsuback));
+        assert_eq!(buf[..5], [0xB0, 0x0B, 0x00, 0x02, 0x07]);
+    }
+
+    #[test]
+    fn test_max_packet_size() {
+        let mut codec = Codec { max_packet_size: 1024 };
+
+        // Rejected from the fixed header, before the payload is buffered
+        let mut src = BytesMut::from(&[0x30, 0x80, 0x80, 0x40][..]);
+        assert!(matches!(codec.decode(&mut src), Err(Error::PacketTooLarge(1048580, 1024))));
+        assert!(src.capacity() < 1024);
+
+        let mut src = BytesMut::from(&[0xC0, 0x00][..]);
+        assert!(matches!(codec.decode(&mut src), Ok(Some((Packet::PingReq, 2)))));
+    }
+
+    #[test]
+    fn test_truncated_properties() {
+        let mut codec = Codec { max_packet_size: 1024 };
+
+        // PUBLISH "t" with a Message Expiry Interval cut short by the property length
+        let mut src = BytesMut::from(&[0x30, 0x06, 0x00, 0x01, b't', 0x02, 0x02, 0x00][..]);
+        assert!(matches!(codec.decode(&mut src), Err(Error::MalformedPacket)));
+
+        // DISCONNECT with a Session Expiry Interval cut short
+        let mut src = BytesMut::from(&[0xE0, 0x04, 0x00, 0x02, 0x11, 0x00][..]);
+        assert!(matches!(codec.decode(&mut src), Err(Error::MalformedPacket)));
+
+        // PUBLISH with the topic length cut short or longer than the packet
+        for packet in [&[0x30, 0x01, 0x00][..], &[0x30, 0x02, 0x00, 0x05]] {
+            let mut src = BytesMut::from(packet);
+            assert!(matches!(codec.decode(&mut src), Err(Error::MalformedPacket)));
+        }
+
+        // UNSUBSCRIBE with a filter longer than the packet
+        let mut src = BytesMut::from(&[0xA2, 0x06, 0x00, 0x01, 0x00, 0x00, 0x09, b'a'][..]);
+        assert!(matches!(codec.decode(&mut src), Err(Error::MalformedPacket)));
+    }
+}
@@ -1,3 +1,4 @@
+use super::AckProperties;
 use crate::protocol::{encode_len, encode_string, len_len, ConnAck, Error, PacketType, Property};
 use bytes::{BufMut, BytesMut};
 
@@ -42,6 +43,16 @@ pub struct ConnAckProperties {
     pub shared_sub_available: Option<u8>,
 }
 
+impl From<AckProperties> for ConnAckProperties {
+    fn from(prop: AckProperties) -> Self {
+        Self {
+            reason_string: prop.reason_string,
+            user_property: prop.user_property,
+            ..Default::default()
+        }
+    }
+}
+
 impl ConnAckProperties {
     pub fn encode(self, dst: &mut BytesMut) {
         if let Some(session_expiry_interval) = self.session_expiry_interval {
@@ -0,0 +1,143 @@
+use super::{properties, AckProperties};
+use crate::protocol::{
+    decode_string, encode_len, encode_string, len_len, Disconnect, Error, PacketType, Property,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+pub fn decode(mut src: Bytes) -> Result<Disconnect, Error> {
+    let mut disconnect = Disconnect { ..Default::default() };
+
+    // The Reason Code and Property Length can be omitted if the Reason Code is 0x00
+    if src.has_remaining() {
+        disconnect.reason_code = src.get_u8();
+    }
+    if src.has_remaining() {
+        disconnect.properties = DisconnectProperties::decode(&mut src)?;
+    }
+
+    Ok(disconnect)
+}
+
+pub fn encode(packet: Disconnect, dst: &mut BytesMut) -> Result<(), Error> {
+    let prop_len = packet.properties.as_ref().map_or(0, |prop| prop.len());
+
+    dst.put_u8((PacketType::Disconnect as u8) << 4);
+    if packet.reason_code == 0 && prop_len == 0 {
+        encode_len(dst, 0)?;
+        return Ok(());
+    }
+
+    let len = 1 + len_len(prop_len) + prop_len;
+    encode_len(dst, len)?;
+    dst.put_u8(packet.reason_code);
+
+    encode_len(dst, prop_len)?;
+    if let Some(prop) = packet.properties {
+        prop.encode(dst);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct DisconnectProperties {
+    pub session_expiry_interval: Option<u32>,
+    pub reason_string: Option<String>,
+    pub user_property: Vec<(String, String)>,
+    pub server_reference: Option<String>,
+}
+
+impl From<AckProperties> for DisconnectProperties {
+    fn from(prop: AckProperties) -> Self {
+        Self {
+            reason_string: prop.reason_string,
+            user_property: prop.user_property,
+            ..Default::default()
+        }
+    }
+}
+
+impl DisconnectProperties {
+    fn decode(src: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut src = match properties(src)? {
+            Some(src) => src,
+            None => return Ok(None),
+        };
+        let mut prop = Self::default();
+
+        while src.has_remaining() {
+            let id = src.get_u8();
+            let property = Property::try_from(id).map_err(|_| Error::MalformedPacket)?;
+            match property {
+                Property::SessionExpiryInterval => {
+                    if src.remaining() < 4 {
+                        return Err(Error::MalformedPacket);
+                    }
+                    prop.session_expiry_interval = Some(src.get_u32());
+                }
+
+                Property::ReasonString => {
+                    prop.reason_string = Some(decode_string(&mut src)?);
+                }
+
+                Property::UserProperty => {
+                    let k = decode_string(&mut src)?;
+                    let v = decode_string(&mut src)?;
+                    prop.user_property.push((k, v));
+                }
+
+                Property::ServerReference => {
+                    prop.server_reference = Some(decode_string(&mut src)?);
+                }
+                _ => return Err(Error::MalformedPacket),
+            }
+        }
+
+        Ok(Some(prop))
+    }
+
+    pub fn encode(self, dst: &mut BytesMut) {
+        if let Some(session_expiry_interval) = self.session_expiry_interval {
+            dst.put_u8(Property::SessionExpiryInterval as u8);
+            dst.put_u32(session_expiry_interval);
+        }
+
+        if let Some(reason_string) = self.reason_string {
+            dst.put_u8(Property::ReasonString as u8);
+            encode_string(dst, &reason_string);
+        }
+
+        for (k, v) in self.user_property.iter() {
+            dst.put_u8(Property::UserProperty as u8);
+            encode_string(dst, k);
+            encode_string(dst, v);
+        }
+
+        if let Some(server_reference) = self.server_reference {
+            dst.put_u8(Property::ServerReference as u8);
+            encode_string(dst, &server_reference);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+
+        if self.session_expiry_interval.is_some() {
+            len += 1 + 4;
+        }
+
+        if let Some(ref reason_string) = self.reason_string {
+            len += 1 + 2 + reason_string.len();
+        }
+
+        for (k, v) in self.user_property.iter() {
+            len += 1 + 2 + k.len() + 2 + v.len();
+        }
+
+        if let Some(ref server_reference) = self.server_reference {
+            len += 1 + 2 + server_reference.len();
+        }
+
+        len
+    }
+}
@@ -1,7 +1,40 @@
+mod auth;
 mod codec;
 mod connack;
 mod connect;
+mod disconnect;
+mod puback;
+mod publish;
+mod suback;
+mod subscribe;
+mod unsuback;
+mod unsubscribe;
 
+pub use auth::AuthProperties;
 pub use codec::Codec;
 pub use connack::ConnAckProperties;
 pub use connect::{ConnectProperties, WillProperties};
+pub use disconnect::DisconnectProperties;
+pub use puback::AckProperties;
+pub use publish::PublishProperties;
+pub use subscribe::SubscribeProperties;
+pub use unsubscribe::UnsubscribeProperties;
+
+use crate::protocol::{decode_len, Error};
+use bytes::{Buf, Bytes};
+
+// Split properties from the packet, None if there are no properties
+fn properties(src: &mut Bytes) -> Result<Option<Bytes>, Error> {
+    let (len, len_len) = match decode_len(src.as_ref())? {
+        Some(len) => len,
+        None => return Err(Error::MalformedPacket),
+    };
+    src.advance(len_len);
+    if src.remaining() < len {
+        return Err(Error::MalformedPacket);
+    }
+    if len == 0 {
+        return Ok(None);
+    }
+    Ok(Some(src.split_to(len)))
+}
@@ -0,0 +1,121 @@
+use super::properties;
+use crate::protocol::{
+    decode_string, encode_len, encode_string, len_len, Error, PacketType, Property, PubAck,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+pub fn decode(mut src: Bytes) -> Result<PubAck, Error> {
+    if src.remaining() < 2 {
+        return Err(Error::MalformedPacket);
+    }
+    let mut packet = PubAck { packet_id: src.get_u16(), ..Default::default() };
+
+    // The Reason Code and Property Length can be omitted if the Reason Code is 0x00
+    if src.has_remaining() {
+        packet.reason_code = src.get_u8();
+    }
+    if src.has_remaining() {
+        packet.properties = AckProperties::decode(&mut src)?;
+    }
+
+    Ok(packet)
+}
+
+pub fn encode(packet_type: PacketType, packet: PubAck, dst: &mut BytesMut) -> Result<(), Error> {
+    let prop_len = packet.properties.as_ref().map_or(0, |prop| prop.len());
+
+    // PUBREL has fixed header flags 0b0010
+    let flags = if matches!(packet_type, PacketType::PubRel) { 0x02 } else { 0x00 };
+    dst.put_u8((packet_type as u8) << 4 | flags);
+
+    if packet.reason_code == 0 && prop_len == 0 {
+        encode_len(dst, 2)?;
+        dst.put_u16(packet.packet_id);
+        return Ok(());
+    }
+
+    let len = 2 + 1 + len_len(prop_len) + prop_len;
+    encode_len(dst, len)?;
+    dst.put_u16(packet.packet_id);
+    dst.put_u8(packet.reason_code);
+
+    encode_len(dst, prop_len)?;
+    if let Some(prop) = packet.properties {
+        prop.encode(dst);
+    }
+
+    Ok(())
+}
+
+// Properties of PUBACK, PUBREC, PUBREL, PUBCOMP, SUBACK and UNSUBACK
+#[derive(Debug, Default, Clone)]
+pub struct AckProperties {
+    pub reason_string: Option<String>,
+    pub user_property: Vec<(String, String)>,
+}
+
+impl AckProperties {
+    pub fn new<R: Into<String>>(reason_string: R) -> Self {
+        Self { reason_string: Some(reason_string.into()), user_property: Vec::new() }
+    }
+
+    // Append a user property
+    pub fn with<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Self {
+        self.user_property.push((k.into(), v.into()));
+        self
+    }
+
+    fn decode(src: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut src = match properties(src)? {
+            Some(src) => src,
+            None => return Ok(None),
+        };
+        let mut prop = Self::default();
+
+        while src.has_remaining() {
+            let id = src.get_u8();
+            let property = Property::try_from(id).map_err(|_| Error::MalformedPacket)?;
+            match property {
+                Property::ReasonString => {
+                    prop.reason_string = Some(decode_string(&mut src)?);
+                }
+
+                Property::UserProperty => {
+                    let k = decode_string(&mut src)?;
+                    let v = decode_string(&mut src)?;
+                    prop.user_property.push((k, v));
+                }
+                _ => return Err(Error::MalformedPacket),
+            }
+        }
+
+        Ok(Some(prop))
+    }
+
+    pub fn encode(self, dst: &mut BytesMut) {
+        if let Some(reason_string) = self.reason_string {
+            dst.put_u8(Property::ReasonString as u8);
+            encode_string(dst, &reason_string);
+        }
+
+        for (k, v) in self.user_property.iter() {
+            dst.put_u8(Property::UserProperty as u8);
+            encode_string(dst, k);
+            encode_string(dst, v);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+
+        if let Some(ref reason_string) = self.reason_string {
+            len += 1 + 2 + reason_string.len();
+        }
+
+        for (k, v) in self.user_property.iter() {
+            len += 1 + 2 + k.len() + 2 + v.len();
+        }
+
+        len
+    }
+}
@@ -0,0 +1,218 @@
+use super::properties;
+use crate::protocol::{
+    decode_binary, decode_len, decode_string, encode_binary, encode_len, encode_string, len_len,
+    Error, PacketType, Property, Publish, QoS,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+pub fn decode(flags: u8, mut src: Bytes) -> Result<Publish, Error> {
+    let mut publish = Publish { ..Default::default() };
+
+    // Fixed header flags
+    publish.dup = flags & 0x08 > 0;
+    let qos = (flags & 0x06) >> 1;
+    publish.qos = QoS::try_from(qos).map_err(|_| Error::Protocol(format!("[QoS: {}]", qos)))?;
+    publish.retain = flags & 0x01 > 0;
+
+    // Topic Name
+    publish.topic = decode_string(&mut src)?;
+
+    // Packet Identifier
+    if publish.qos != QoS::AtMostOnce {
+        if src.remaining() < 2 {
+            return Err(Error::MalformedPacket);
+        }
+        publish.packet_id = Some(src.get_u16());
+    }
+
+    // Properties
+    publish.properties = PublishProperties::decode(&mut src)?;
+
+    // Payload
+    publish.payload = src;
+
+    Ok(publish)
+}
+
+pub fn encode(packet: Publish, dst: &mut BytesMut) -> Result<(), Error> {
+    let prop_len = packet.properties.as_ref().map_or(0, |prop| prop.len());
+
+    let mut len = 2 + packet.topic.len() + len_len(prop_len) + prop_len + packet.payload.len();
+    if packet.packet_id.is_some() {
+        len += 2;
+    }
+
+    let flags = (packet.dup as u8) << 3 | (packet.qos as u8) << 1 | packet.retain as u8;
+    dst.put_u8((PacketType::Publish as u8) << 4 | flags);
+    encode_len(dst, len)?;
+    encode_string(dst, &packet.topic);
+    if let Some(packet_id) = packet.packet_id {
+        dst.put_u16(packet_id);
+    }
+
+    encode_len(dst, prop_len)?;
+    if let Some(prop) = packet.properties {
+        prop.encode(dst);
+    }
+
+    dst.extend_from_slice(&packet.payload);
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PublishProperties {
+    pub payload_format_indicator: Option<u8>,
+    pub message_expiry_interval: Option<u32>,
+    pub topic_alias: Option<u16>,
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Bytes>,
+    pub user_property: Vec<(String, String)>,
+    pub sub_identifiers: Vec<usize>,
+    pub content_type: Option<String>,
+}
+
+impl PublishProperties {
+    fn decode(src: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut src = match properties(src)? {
+            Some(src) => src,
+            None => return Ok(None),
+        };
+        let mut prop = Self::default();
+
+        while src.has_remaining() {
+            let id = src.get_u8();
+            let property = Property::try_from(id).map_err(|_| Error::MalformedPacket)?;
+            match property {
+                Property::PayloadFormatIndicator => {
+                    if src.remaining() < 1 {
+                        return Err(Error::MalformedPacket);
+                    }
+                    prop.payload_format_indicator = Some(src.get_u8());
+                }
+
+                Property::MessageExpiryInterval => {
+                    if src.remaining() < 4 {
+                        return Err(Error::MalformedPacket);
+                    }
+                    prop.message_expiry_interval = Some(src.get_u32());
+                }
+
+                Property::TopicAlias => {
+                    if src.remaining() < 2 {
+                        return Err(Error::MalformedPacket);
+                    }
+                    prop.topic_alias = Some(src.get_u16());
+                }
+
+                Property::ResponseTopic => {
+                    prop.response_topic = Some(decode_string(&mut src)?);
+                }
+
+                Property::CorrelationData => {
+                    prop.correlation_data = Some(decode_binary(&mut src)?);
+                }
+
+                Property::UserProperty => {
+                    let k = decode_string(&mut src)?;
+                    let v = decode_string(&mut src)?;
+                    prop.user_property.push((k, v));
+                }
+
+                Property::SubIdentifier => {
+                    let (id, len_len) = decode_len(src.as_ref())?.ok_or(Error::MalformedPacket)?;
+                    src.advance(len_len);
+                    prop.sub_identifiers.push(id);
+                }
+
+                Property::ContentType => {
+                    prop.content_type = Some(decode_string(&mut src)?);
+                }
+                _ => return Err(Error::MalformedPacket),
+            }
+        }
+
+        Ok(Some(prop))
+    }
+
+    pub fn encode(self, dst: &mut BytesMut) {
+        if let Some(payload_format_indicator) = self.payload_format_indicator {
+            dst.put_u8(Property::PayloadFormatIndicator as u8);
+            dst.put_u8(payload_format_indicator);
+        }
+
+        if let Some(message_expiry_interval) = self.message_expiry_interval {
+            dst.put_u8(Property::MessageExpiryInterval as u8);
+            dst.put_u32(message_expiry_interval);
+        }
+
+        if let Some(topic_alias) = self.topic_alias {
+            dst.put_u8(Property::TopicAlias as u8);
+            dst.put_u16(topic_alias);
+        }
+
+        if let Some(response_topic) = self.response_topic {
+            dst.put_u8(Property::ResponseTopic as u8);
+            encode_string(dst, &response_topic);
+        }
+
+        if let Some(correlation_data) = self.correlation_data {
+            dst.put_u8(Property::CorrelationData as u8);
+            encode_binary(dst, &correlation_data);
+        }
+
+        for (k, v) in self.user_property.iter() {
+            dst.put_u8(Property::UserProperty as u8);
+            encode_string(dst, k);
+            encode_string(dst, v);
+        }
+
+        for id in self.sub_identifiers {
+            dst.put_u8(Property::SubIdentifier as u8);
+            // Variable byte integer never exceeds 268,435,455
+            let _ = encode_len(dst, id);
+        }
+
+        if let Some(content_type) = self.content_type {
+            dst.put_u8(Property::ContentType as u8);
+            encode_string(dst, &content_type);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+
+        if self.payload_format_indicator.is_some() {
+            len += 1 + 1;
+        }
+
+        if self.message_expiry_interval.is_some() {
+            len += 1 + 4;
+        }
+
+        if self.topic_alias.is_some() {
+            len += 1 + 2;
+        }
+
+        if let Some(ref response_topic) = self.response_topic {
+            len += 1 + 2 + response_topic.len();
+        }
+
+        if let Some(ref correlation_data) = self.correlation_data {
+            len += 1 + 2 + correlation_data.len();
+        }
+
+        for (k, v) in self.user_property.iter() {
+            len += 1 + 2 + k.len() + 2 + v.len();
+        }
+
+        for id in self.sub_identifiers.iter() {
+            len += 1 + len_len(*id);
+        }
+
+        if let Some(ref content_type) = self.content_type {
+            len += 1 + 2 + content_type.len();
+        }
+
+        len
+    }
+}
@@ -0,0 +1,78 @@
+use super::properties;
+use crate::protocol::{
+    decode_len, decode_string, Error, Property, QoS, Subscribe, SubscribeOptions,
+};
+use bytes::{Buf, Bytes};
+
+pub fn decode(mut src: Bytes) -> Result<Subscribe, Error> {
+    if src.remaining() < 2 {
+        return Err(Error::MalformedPacket);
+    }
+    let mut subscribe = Subscribe { packet_id: src.get_u16(), ..Default::default() };
+
+    // Properties
+    subscribe.properties = SubscribeProperties::decode(&mut src)?;
+
+    // Topic Filters
+    while src.has_remaining() {
+        let filter = decode_string(&mut src)?;
+        if !src.has_remaining() {
+            return Err(Error::MalformedPacket);
+        }
+        let options = src.get_u8();
+        if options & 0xC0 > 0 {
+            return Err(Error::MalformedPacket);
+        }
+        let qos = options & 0x03;
+        let options = SubscribeOptions {
+            qos: QoS::try_from(qos).map_err(|_| Error::Protocol(format!("[QoS: {}]", qos)))?,
+            no_local: options & 0x04 > 0,
+            retain_as_published: options & 0x08 > 0,
+            retain_handling: (options & 0x30) >> 4,
+        };
+        subscribe.filters.push((filter, options));
+    }
+
+    if subscribe.filters.is_empty() {
+        return Err(Error::Protocol("SUBSCRIBE without topic filters".into()));
+    }
+
+    Ok(subscribe)
+}
+
+#[derive(Debug, Default)]
+pub struct SubscribeProperties {
+    pub sub_identifier: Option<usize>,
+    pub user_property: Vec<(String, String)>,
+}
+
+impl SubscribeProperties {
+    fn decode(src: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut src = match properties(src)? {
+            Some(src) => src,
+            None => return Ok(None),
+        };
+        let mut prop = Self::default();
+
+        while src.has_remaining() {
+            let id = src.get_u8();
+            let property = Property::try_from(id).map_err(|_| Error::MalformedPacket)?;
+            match property {
+                Property::SubIdentifier => {
+                    let (id, len_len) = decode_len(src.as_ref())?.ok_or(Error::MalformedPacket)?;
+                    src.advance(len_len);
+                    prop.sub_identifier = Some(id);
+                }
+
+                Property::UserProperty => {
+                    let k = decode_string(&mut src)?;
+                    let v = decode_string(&mut src)?;
+                    prop.user_property.push((k, v));
+                }
+                _ => return Err(Error::MalformedPacket),
+            }
+        }
+
+        Ok(Some(prop))
+    }
+}
@@ -0,0 +1,19 @@
+use crate::protocol::{encode_len, len_len, Error, PacketType, UnsubAck};
+use bytes::{BufMut, BytesMut};
+
+pub fn encode(packet: UnsubAck, dst: &mut BytesMut) -> Result<(), Error> {
+    let prop_len = packet.properties.as_ref().map_or(0, |prop| prop.len());
+
+    let len = 2 + len_len(prop_len) + prop_len + packet.reason_codes.len();
+    dst.put_u8((PacketType::UnsubAck as u8) << 4);
+    encode_len(dst, len)?;
+    dst.put_u16(packet.packet_id);
+
+    encode_len(dst, prop_len)?;
+    if let Some(prop) = packet.properties {
+        prop.encode(dst);
+    }
+
+    dst.extend_from_slice(&packet.reason_codes);
+    Ok(())
+}
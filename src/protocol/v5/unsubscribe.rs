@@ -0,0 +1,54 @@
+use super::properties;
+use crate::protocol::{decode_string, Error, Property, Unsubscribe};
+use bytes::{Buf, Bytes};
+
+pub fn decode(mut src: Bytes) -> Result<Unsubscribe, Error> {
+    if src.remaining() < 2 {
+        return Err(Error::MalformedPacket);
+    }
+    let mut unsubscribe = Unsubscribe { packet_id: src.get_u16(), ..Default::default() };
+
+    // Properties
+    unsubscribe.properties = UnsubscribeProperties::decode(&mut src)?;
+
+    // Topic Filters
+    while src.has_remaining() {
+        unsubscribe.filters.push(decode_string(&mut src)?);
+    }
+
+    if unsubscribe.filters.is_empty() {
+        return Err(Error::Protocol("UNSUBSCRIBE without topic filters".into()));
+    }
+
+    Ok(unsubscribe)
+}
+
+#[derive(Debug, Default)]
+pub struct UnsubscribeProperties {
+    pub user_property: Vec<(String, String)>,
+}
+
+impl UnsubscribeProperties {
+    fn decode(src: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut src = match properties(src)? {
+            Some(src) => src,
+            None => return Ok(None),
+        };
+        let mut prop = Self::default();
+
+        while src.has_remaining() {
+            let id = src.get_u8();
+            let property = Property::try_from(id).map_err(|_| Error::MalformedPacket)?;
+            match property {
+                Property::UserProperty => {
+                    let k = decode_string(&mut src)?;
+                    let v = decode_string(&mut src)?;
+                    prop.user_property.push((k, v));
+                }
+                _ => return Err(Error::MalformedPacket),
+            }
+        }
+
+        Ok(Some(prop))
+    }
+}
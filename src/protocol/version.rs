@@ -1,10 +1,9 @@
 use super::{decode_len, decode_string, Error, Packet};
 use bytes::{Buf, Bytes};
-use std::fmt::format;
 use tokio_util::bytes::BytesMut;
 use tokio_util::codec::Decoder;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Version {
     V3,
     V5,
@@ -39,7 +38,7 @@ impl Decoder for Codec {
                 let mut bytes = Bytes::copy_from_slice(bytes);
                 let protocol = decode_string(&mut bytes)?;
                 if protocol != "MQTT" && protocol != "MQIsdp" {
-                    return Err(Error::ProtocolError(format!("[protocol: {}]", protocol)));
+                    return Err(Error::Protocol(format!("[protocol: {}]", protocol)));
                 }
 
                 // Decode Level
@@ -0,0 +1,219 @@
+use crate::auth::PeerCert;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// PROXY protocol v2 signature
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// Longest v1 header including CRLF
+const V1_MAX_LEN: usize = 107;
+
+// v2 TLV types
+const PP2_TYPE_SSL: u8 = 0x20;
+const PP2_SUBTYPE_SSL_VERSION: u8 = 0x21;
+const PP2_SUBTYPE_SSL_CN: u8 = 0x22;
+// SSL client flags
+const PP2_CLIENT_SSL: u8 = 0x01;
+const PP2_CLIENT_CERT_CONN: u8 = 0x02;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProxyError {
+    #[error("PROXY header: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid PROXY header: {0}")]
+    Invalid(&'static str),
+}
+
+// TLS the load balancer terminated, from the v2 SSL TLV
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Ssl {
+    pub version: Option<String>,
+    // Common Name of a client certificate the load balancer verified
+    pub cn: Option<String>,
+}
+
+// PROXY protocol header
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Header {
+    // Original client address, None for health checks and unknown address families
+    pub source: Option<SocketAddr>,
+    pub ssl: Option<Ssl>,
+}
+
+impl Header {
+    // Client certificate identity verified by the load balancer
+    pub fn peer_cert(&self) -> Option<PeerCert> {
+        let cn = self.ssl.as_ref()?.cn.clone()?;
+        Some(PeerCert { dn: format!("CN={}", cn), cn: Some(cn), san: None })
+    }
+}
+
+// Read a v1 or v2 header, nothing after it is consumed
+pub async fn read_header<S: AsyncRead + Unpin>(io: &mut S) -> Result<Header, ProxyError> {
+    let mut buf = vec![0u8; SIGNATURE.len()];
+    io.read_exact(&mut buf).await?;
+
+    if buf == SIGNATURE {
+        let mut head = [0u8; 4];
+        io.read_exact(&mut head).await?;
+        let mut body = vec![0u8; u16::from_be_bytes([head[2], head[3]]) as usize];
+        io.read_exact(&mut body).await?;
+        return parse_v2(head[0], head[1], &body);
+    }
+
+    if !buf.starts_with(b"PROXY ") {
+        return Err(ProxyError::Invalid("missing"));
+    }
+    while !buf.ends_with(b"\r\n") {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(ProxyError::Invalid("v1 line too long"));
+        }
+        buf.push(io.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&buf[..buf.len() - 2]).map_err(|_| ProxyError::Invalid("v1"))?;
+    parse_v1(line)
+}
+
+// PROXY TCP4|TCP6 <src ip> <dst ip> <src port> <dst port>, or PROXY UNKNOWN
+fn parse_v1(line: &str) -> Result<Header, ProxyError> {
+    let invalid = || ProxyError::Invalid("v1");
+    let mut fields = line.split(' ').skip(1);
+    match fields.next() {
+        Some("UNKNOWN") => return Ok(Header::default()),
+        Some("TCP4") | Some("TCP6") => {}
+        _ => return Err(invalid()),
+    }
+    let ip: IpAddr = fields.next().and_then(|ip| ip.parse().ok()).ok_or_else(invalid)?;
+    let _dst = fields.next().ok_or_else(invalid)?;
+    let port: u16 = fields.next().and_then(|port| port.parse().ok()).ok_or_else(invalid)?;
+    Ok(Header { source: Some(SocketAddr::new(ip, port)), ssl: None })
+}
+
+fn parse_v2(ver_cmd: u8, family: u8, body: &[u8]) -> Result<Header, ProxyError> {
+    if ver_cmd >> 4 != 2 {
+        return Err(ProxyError::Invalid("v2 version"));
+    }
+    // LOCAL connections come from the proxy itself
+    let local = match ver_cmd & 0x0F {
+        0 => true,
+        1 => false,
+        _ => return Err(ProxyError::Invalid("v2 command")),
+    };
+
+    let (source, tlvs) = match family >> 4 {
+        // AF_INET: src, dst, src port, dst port
+        1 if body.len() >= 12 => {
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&body[..4]).unwrap());
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            (Some(SocketAddr::new(ip.into(), port)), &body[12..])
+        }
+        // AF_INET6
+        2 if body.len() >= 36 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&body[..16]).unwrap());
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            (Some(SocketAddr::new(ip.into(), port)), &body[36..])
+        }
+        // AF_UNIX
+        3 if body.len() >= 216 => (None, &body[216..]),
+        0 => (None, body),
+        _ => return Err(ProxyError::Invalid("v2 address")),
+    };
+
+    let mut ssl = None;
+    for (kind, value) in tlvs_iter(tlvs)? {
+        if kind == PP2_TYPE_SSL {
+            ssl = parse_ssl(value)?;
+        }
+    }
+    Ok(Header { source: source.filter(|_| !local), ssl })
+}
+
+// client flags, verify result, sub TLVs
+fn parse_ssl(value: &[u8]) -> Result<Option<Ssl>, ProxyError> {
+    if value.len() < 5 {
+        return Err(ProxyError::Invalid("v2 ssl tlv"));
+    }
+    let client = value[0];
+    if client & PP2_CLIENT_SSL == 0 {
+        return Ok(None);
+    }
+    let verified = client & PP2_CLIENT_CERT_CONN != 0 && value[1..5] == [0, 0, 0, 0];
+
+    let mut ssl = Ssl::default();
+    for (kind, value) in tlvs_iter(&value[5..])? {
+        let value = String::from_utf8_lossy(value).into_owned();
+        match kind {
+            PP2_SUBTYPE_SSL_VERSION => ssl.version = Some(value),
+            PP2_SUBTYPE_SSL_CN if verified => ssl.cn = Some(value),
+            _ => {}
+        }
+    }
+    Ok(Some(ssl))
+}
+
+// Type, 2 byte length, value
+fn tlvs_iter(mut src: &[u8]) -> Result<Vec<(u8, &[u8])>, ProxyError> {
+    let mut tlvs = Vec::new();
+    while !src.is_empty() {
+        if src.len() < 3 {
+            return Err(ProxyError::Invalid("v2 tlv"));
+        }
+        let len = u16::from_be_bytes([src[1], src[2]]) as usize;
+        let value = src.get(3..3 + len).ok_or(ProxyError::Invalid("v2 tlv"))?;
+        tlvs.push((src[0], value));
+        src = &src[3 + len..];
+    }
+    Ok(tlvs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_v1() {
+        let mut src: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 1883\r\nMQTT";
+        let header = read_header(&mut src).await.unwrap();
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(src, b"MQTT");
+
+        let mut src: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 1883\r\n";
+        let header = read_header(&mut src).await.unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:4000".parse().unwrap()));
+
+        let mut src: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut src).await.unwrap(), Header::default());
+
+        let mut src: &[u8] = b"\x10\x0c\x00\x04MQTT\x05\x02\x00\x3c";
+        assert!(matches!(read_header(&mut src).await, Err(ProxyError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    async fn test_v2() {
+        let mut ssl = vec![PP2_CLIENT_SSL | PP2_CLIENT_CERT_CONN, 0, 0, 0, 0];
+        ssl.extend([PP2_SUBTYPE_SSL_VERSION, 0, 7]);
+        ssl.extend(b"TLSv1.3");
+        ssl.extend([PP2_SUBTYPE_SSL_CN, 0, 8]);
+        ssl.extend(b"device-1");
+
+        let mut body = vec![192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x07, 0x5B];
+        body.extend([PP2_TYPE_SSL, 0, ssl.len() as u8]);
+        body.extend(&ssl);
+        let mut data = SIGNATURE.to_vec();
+        data.extend([0x21, 0x11, 0, body.len() as u8]);
+        data.extend(&body);
+        data.extend(b"MQTT");
+
+        let mut src: &[u8] = &data;
+        let header = read_header(&mut src).await.unwrap();
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(header.ssl.as_ref().unwrap().version.as_deref(), Some("TLSv1.3"));
+        assert_eq!(header.peer_cert().unwrap().cn.as_deref(), Some("device-1"));
+        assert_eq!(src, b"MQTT");
+
+        // LOCAL command, health checks from the proxy itself
+        let mut data = SIGNATURE.to_vec();
+        data.extend([0x20, 0x00, 0, 0]);
+        let mut src: &[u8] = &data;
+        assert_eq!(read_header(&mut src).await.unwrap(), Header::default());
+    }
+}
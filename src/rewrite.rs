@@ -0,0 +1,99 @@
+use crate::broker::{matches, valid_level};
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+// Topic rewrite action
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Publish,
+    Subscribe,
+    All,
+}
+
+// Topic rewrite rule
+#[derive(Debug, Deserialize, Clone)]
+pub struct Rewrite {
+    pub action: Action,
+    // Topic filter the rule applies to
+    pub source: String,
+    #[serde(deserialize_with = "Rewrite::deserialize_re")]
+    pub re: Regex,
+    // Destination template, supports $1..$N captures and ${clientid} / ${username}
+    pub dest: String,
+}
+
+impl Rewrite {
+    // Deserialize for regex
+    fn deserialize_re<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+        let re = String::deserialize(deserializer)?;
+        Regex::new(&re).map_err(serde::de::Error::custom)
+    }
+
+    // Rewrite the topic with the first rule matching the action and topic, if any
+    pub fn apply(
+        rules: &[Rewrite],
+        action: Action,
+        topic: &str,
+        client_id: &str,
+        username: Option<&str>,
+    ) -> Option<String> {
+        let rule = rules.iter().find(|rule| {
+            (rule.action == action || rule.action == Action::All)
+                && matches(&rule.source, topic)
+                && rule.re.is_match(topic)
+        })?;
+
+        // A value holding wildcards or separators would change the shape of the topic
+        let username = username.unwrap_or_default();
+        if (rule.dest.contains("${clientid}") && !valid_level(client_id))
+            || (rule.dest.contains("${username}") && !valid_level(username))
+        {
+            return None;
+        }
+
+        // Placeholder values are escaped so they are not taken as capture groups
+        let dest = rule
+            .dest
+            .replace("${clientid}", &client_id.replace('$', "$$"))
+            .replace("${username}", &username.replace('$', "$$"));
+        Some(rule.re.replace(topic, dest.as_str()).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(action: Action, source: &str, re: &str, dest: &str) -> Rewrite {
+        Rewrite { action, source: source.into(), re: Regex::new(re).unwrap(), dest: dest.into() }
+    }
+
+    #[test]
+    fn test_apply() {
+        let rules = vec![
+            rule(Action::Publish, "x/#", "^x/(.+)$", "devices/x/$1"),
+            rule(Action::All, "y/+/z", "^y/(.+)/z$", "${clientid}/${username}/$1"),
+            rule(Action::Subscribe, "x/#", "^x/(.+)$", "sub/$1"),
+        ];
+
+        let apply = |action, topic| Rewrite::apply(&rules, action, topic, "c1", Some("u$1"));
+        assert_eq!(apply(Action::Publish, "x/y/z"), Some("devices/x/y/z".into()));
+        assert_eq!(apply(Action::Subscribe, "x/#"), Some("sub/#".into()));
+        assert_eq!(apply(Action::Subscribe, "y/a/z"), Some("c1/u$1/a".into()));
+        assert_eq!(apply(Action::Publish, "y/a/b/z"), None);
+        assert_eq!(apply(Action::Publish, "a/b"), None);
+
+        // Placeholders are only substituted with single level values
+        for (client_id, username) in [("c/1", "u"), ("c1", "u/#"), ("+", "u"), ("c1", "#")] {
+            assert_eq!(
+                Rewrite::apply(&rules, Action::Publish, "y/a/z", client_id, Some(username)),
+                None
+            );
+        }
+        assert_eq!(
+            Rewrite::apply(&rules, Action::Publish, "x/a", "c/1", Some("#")),
+            Some("devices/x/a".into())
+        );
+    }
+}
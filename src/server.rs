@@ -5,6 +5,7 @@ use nix::unistd::Pid;
 use std::fs;
 use std::process::exit;
 use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::rustls::crypto::{aws_lc_rs, CryptoProvider};
 use tracing::{error, info};
 
 const PID_FILE: &str = concat!("/tmp/", env!("CARGO_PKG_NAME"), ".pid");
@@ -78,8 +79,17 @@ impl Server {
         Self::start();
     }
 
-    // reload configuration
-    pub fn reload() {}
+    // reload TLS certificates of the running server
+    pub fn reload() {
+        let pid = fs::read_to_string(PID_FILE).ok().and_then(|pid| pid.trim().parse::<i32>().ok());
+        let Some(pid) = pid else {
+            println!("{} server is not running", env!("CARGO_PKG_NAME"));
+            return;
+        };
+        if let Err(e) = kill(Pid::from_raw(pid), Signal::SIGHUP) {
+            println!("{} [PID: {}]", e, pid);
+        }
+    }
 
     // show server status
     pub fn status() {}
@@ -88,7 +98,11 @@ impl Server {
 // run server
 async fn run() {
     log::init().await;
-    let ctx = Context::new();
+
+    // Both rustls crypto backends are linked in, so the default has to be chosen
+    let _ = CryptoProvider::install_default(aws_lc_rs::default_provider());
+
+    let ctx = Context::new().await;
 
     // Web Server
     let web_ctx = ctx.clone();
@@ -116,17 +130,17 @@ async fn run() {
         tokio::select! {
             _ = sigint.recv() => {
                 info!("server received SIGINT signal");
-                let  _ = ctx.shutdown();
+                ctx.shutdown();
                 break;
             }
             _ = sigterm.recv() => {
                 info!("server received SIGTERM signal");
-                let  _ = ctx.shutdown();
+                ctx.shutdown();
                 break;
             }
             _ = sighup.recv() => {
                 info!("server received SIGHUP signal");
-                Server::reload();
+                ctx.reload();
             }
         }
     }
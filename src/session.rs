@@ -1,15 +1,638 @@
-use crate::Context;
+use crate::acl::{AclCache, Client, DenyAction};
+use crate::auth::{AclAction, AuthExchange, Grant, Permission, Step, TopicRule};
+use crate::broker::{Delivery, Message};
+use crate::delayed::{self, Delayed};
+use crate::metrics::Metrics;
+use crate::protocol::v5::{AckProperties, AuthProperties};
+use crate::protocol::{
+    valid_filter, valid_topic, Auth, Connect, Disconnect, Error, Packet, PubAck, Publish, QoS,
+    ReasonCode, SubAck, Subscribe, SubscribeOptions, UnsubAck, Unsubscribe,
+};
+use crate::rewrite::{Action, Rewrite};
+use crate::{sys, Context, Stream};
+use serde_json::json;
+use std::collections::{HashSet, VecDeque};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Duration, Instant};
+use tracing::{debug, warn};
 
-pub struct Session {}
+pub struct Session<S> {
+    ctx: Context,
+    stream: Stream<S>,
+    client_id: String,
+    username: Option<String>,
+    keepalive: u16,
+    // Superuser flag and topic rules granted by the authenticator
+    grant: Grant,
+    acl_cache: AclCache,
+    // Enhanced authentication method used on CONNECT and the re-authentication in progress
+    auth_method: Option<String>,
+    reauth: Option<Box<dyn AuthExchange>>,
+    // Request Problem Information, reason strings and user properties are sent only when set
+    problem_info: bool,
+    // QoS 2 packet ids waiting for PUBREL
+    awaiting_rel: HashSet<u16>,
+    // Outgoing QoS 1 and 2 packet ids waiting for acknowledgement
+    inflight: HashSet<u16>,
+    packet_id: u16,
+    // Unacknowledged deliveries allowed, the client Receive Maximum within max_receive
+    receive_max: u16,
+    // QoS 1 and 2 deliveries waiting for an acknowledgement to make room, up to max_mqueue_len
+    pending: VecDeque<(Message, QoS, bool)>,
+    max_pending: usize,
+}
+
+impl<S> Session<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(
+        ctx: Context,
+        stream: Stream<S>,
+        connect: Connect,
+        problem_info: bool,
+        grant: Grant,
+        auth_method: Option<String>,
+        receive_max: u16,
+    ) -> Self {
+        Self {
+            ctx,
+            stream,
+            client_id: connect.client_id,
+            username: connect.username,
+            keepalive: connect.keepalive,
+            grant,
+            acl_cache: AclCache::default(),
+            auth_method,
+            reauth: None,
+            problem_info,
+            awaiting_rel: HashSet::new(),
+            inflight: HashSet::new(),
+            packet_id: 0,
+            receive_max,
+            pending: VecDeque::new(),
+            max_pending: 0,
+        }
+    }
+
+    pub async fn run(mut self) {
+        debug!(
+            "Session[{}] connected from {} on {}, superuser: {}, acl rules: {}",
+            self.client_id,
+            self.stream.addr,
+            self.stream.listener,
+            self.grant.is_superuser,
+            self.grant.acl.len()
+        );
+
+        self.max_pending = self.ctx.config().await.mqtt.max_mqueue_len;
+        let (tx, rx) = mpsc::channel(self.max_pending.max(1));
+        let conn_id = self.ctx.broker().connect(&self.client_id, tx).await;
+        let payload = json!({
+            "ipaddress": self.stream.addr.to_string(),
+            "proto_ver": format!("{:?}", self.stream.version),
+            "keepalive": self.keepalive,
+            "is_superuser": self.grant.is_superuser,
+        });
+        sys::event(&self.ctx, &self.client_id, "connected", payload).await;
+
+        let reason = match self.run_loop(rx).await {
+            Ok(()) => "client disconnect".to_string(),
+            Err(e) => e.to_string(),
+        };
+
+        self.ctx.broker().disconnect(&self.client_id, conn_id).await;
+        sys::event(&self.ctx, &self.client_id, "disconnected", json!({ "reason": reason })).await;
+
+        debug!("Session[{}] closed: {}", self.client_id, reason);
+    }
+
+    async fn run_loop(&mut self, mut rx: mpsc::Receiver<Delivery>) -> Result<(), Error> {
+        let mut shutdown = self.ctx.subscribe();
+        self.auto_subscribe().await?;
+
+        // Keep Alive, the server closes the connection after one and a half times the period
+        // without a packet from the client, deliveries to it do not move the deadline
+        let check_keepalive = self.keepalive > 0;
+        let keepalive = Duration::from_millis(self.keepalive as u64 * 1500);
+        let mut deadline = Instant::now() + keepalive;
+
+        loop {
+            tokio::select! {
+                res = async {
+                    self.stream.throttle().await;
+                    self.stream.recv().await
+                } => {
+                    let packet = match res {
+                        Ok((packet, _)) => packet,
+                        Err(e @ Error::PacketTooLarge(..)) => {
+                            let prop = AckProperties::new(e.to_string());
+                            return self.disconnect(ReasonCode::PacketTooLarge, prop).await;
+                        }
+                        Err(e) => return Err(e),
+                    };
+
+                    // Time reads are paused by the rate limits does not count
+                    let now = Instant::now();
+                    deadline = self.stream.paused_until().map_or(now, |at| at.max(now)) + keepalive;
+
+                    match packet {
+                        Packet::Publish(publish) => self.publish(publish).await?,
+                        Packet::PubAck(puback) => self.release(puback.packet_id).await?,
+                        Packet::PubRec(pubrec) => self.pubrec(pubrec).await?,
+                        Packet::PubRel(pubrel) => self.pubrel(pubrel).await?,
+                        Packet::PubComp(pubcomp) => self.release(pubcomp.packet_id).await?,
+                        Packet::Subscribe(subscribe) => self.subscribe(subscribe).await?,
+                        Packet::Unsubscribe(unsubscribe) => self.unsubscribe(unsubscribe).await?,
+                        Packet::PingReq => self.stream.send(Packet::PingResp).await?,
+                        Packet::Disconnect(_) => return Ok(()),
+                        Packet::Auth(auth) => self.auth(auth).await?,
+                        packet => {
+                            let prop = AckProperties::new(format!(
+                                "unexpected {:?} packet",
+                                packet
+                            ));
+                            return self.disconnect(ReasonCode::ProtocolError, prop).await;
+                        }
+                    }
+                }
+                res = rx.recv() => match res {
+                    Some((msg, options)) => self.deliver(msg, options).await?,
+                    None => {
+                        let prop = AckProperties::new("client id connected elsewhere");
+                        return self.disconnect(ReasonCode::SessionTakenOver, prop).await;
+                    }
+                },
+                _ = sleep_until(deadline), if check_keepalive => {
+                    let prop = AckProperties::new(format!(
+                        "no packet received within {}s keep alive",
+                        self.keepalive
+                    ));
+                    return self.disconnect(ReasonCode::KeepAliveTimeout, prop).await;
+                }
+                _ = shutdown.recv() => {
+                    let prop = AckProperties::new("server shutting down");
+                    return self.disconnect(ReasonCode::ServerShuttingDown, prop).await;
+                }
+            }
+        }
+    }
+
+    // Diagnostic properties are only sent when the client requested problem information
+    fn problem(&self, prop: AckProperties) -> Option<AckProperties> {
+        self.problem_info.then_some(prop)
+    }
+
+    // Send DISCONNECT and close the session
+    async fn disconnect(
+        &mut self,
+        reason_code: ReasonCode,
+        prop: AckProperties,
+    ) -> Result<(), Error> {
+        let reason = prop.reason_string.clone().unwrap_or_default();
+        let packet = Packet::Disconnect(Disconnect {
+            reason_code: reason_code as u8,
+            properties: self.problem(prop).map(Into::into),
+        });
+        self.stream.send(packet).await?;
+        Err(Error::Protocol(format!("{:?}: {}", reason_code, reason)))
+    }
+
+    // AUTH, re-authentication with the method used on CONNECT
+    async fn auth(&mut self, auth: Auth) -> Result<(), Error> {
+        let prop = auth.properties.unwrap_or_default();
+        let method = match &self.auth_method {
+            Some(method) if prop.auth_method.as_ref() == Some(method) => method.clone(),
+            _ => {
+                let prop = AckProperties::new("authentication method differs from CONNECT");
+                return self.disconnect(ReasonCode::ProtocolError, prop).await;
+            }
+        };
+
+        if auth.reason_code == ReasonCode::ReAuthenticate as u8 {
+            let cfg = self.ctx.config().await.auth;
+            self.reauth = self.ctx.auth().start(&cfg, &method);
+            if self.reauth.is_none() {
+                let prop =
+                    AckProperties::new(format!("authentication method {} is disabled", method));
+                return self.disconnect(ReasonCode::BadAuthenticationMethod, prop).await;
+            }
+        } else if auth.reason_code != ReasonCode::ContinueAuthentication as u8 {
+            let prop = AckProperties::new(format!("unexpected AUTH reason {}", auth.reason_code));
+            return self.disconnect(ReasonCode::ProtocolError, prop).await;
+        }
+        let Some(exchange) = self.reauth.as_mut() else {
+            let prop = AckProperties::new("no re-authentication in progress");
+            return self.disconnect(ReasonCode::ProtocolError, prop).await;
+        };
+
+        let (reason_code, data) = match exchange.step(&prop.auth_data.unwrap_or_default()).await {
+            Step::Continue(challenge) => (ReasonCode::ContinueAuthentication, Some(challenge)),
+            Step::Success { data, username, grant } => {
+                self.reauth = None;
+                if username.is_some() && username != self.username {
+                    let prop = AckProperties::new("re-authenticated as a different user");
+                    return self.disconnect(ReasonCode::NotAuthorized, prop).await;
+                }
+                debug!("Session[{}] re-authenticated", self.client_id);
+                self.grant = grant;
+                self.acl_cache = AclCache::default();
+                (ReasonCode::Success, data)
+            }
+            Step::Failure(reason) => {
+                return self
+                    .disconnect(ReasonCode::NotAuthorized, AckProperties::new(reason))
+                    .await;
+            }
+        };
+        let properties =
+            AuthProperties { auth_method: Some(method), auth_data: data, ..Default::default() };
+        let packet =
+            Packet::Auth(Auth { reason_code: reason_code as u8, properties: Some(properties) });
+        self.stream.send(packet).await
+    }
+
+    // Rewrite the topic with the configured rules, the result must still be valid
+    async fn rewrite(&self, action: Action, topic: String, valid: fn(&str) -> bool) -> String {
+        let rules = self.ctx.config().await.rewrite;
+        match Rewrite::apply(&rules, action, &topic, &self.client_id, self.username.as_deref()) {
+            Some(dest) if valid(&dest) => {
+                debug!("Session[{}] rewrite {} to {}", self.client_id, topic, dest);
+                dest
+            }
+            Some(dest) => {
+                warn!("Session[{}] rewrite {} to invalid {}", self.client_id, topic, dest);
+                topic
+            }
+            None => topic,
+        }
+    }
+
+    // Unused packet identifier, None when all of them are in flight
+    fn next_packet_id(&mut self) -> Option<u16> {
+        if self.inflight.len() >= u16::MAX as usize {
+            return None;
+        }
+        loop {
+            self.packet_id = self.packet_id.wrapping_add(1);
+            if self.packet_id != 0 && !self.inflight.contains(&self.packet_id) {
+                return Some(self.packet_id);
+            }
+        }
+    }
 
-impl Session {
-    pub fn new() -> Self {
-        Self {}
+    // Deliver a routed message to the client
+    async fn deliver(&mut self, msg: Message, options: SubscribeOptions) -> Result<(), Error> {
+        if options.no_local && msg.from == self.client_id {
+            return Ok(());
+        }
+        let retain = options.retain_as_published && msg.retain;
+        self.send_publish(msg, options.qos, retain).await
     }
 
-    pub async fn run(self) {
-        println!("session running...");
+    async fn send_publish(&mut self, msg: Message, qos: QoS, retain: bool) -> Result<(), Error> {
+        let qos = qos.min(msg.qos);
+        let packet_id = match qos {
+            QoS::AtMostOnce => None,
+            // Held back until the client acknowledges earlier deliveries
+            _ if self.inflight.len() >= self.receive_max as usize => {
+                if self.pending.len() < self.max_pending {
+                    self.pending.push_back((msg, qos, retain));
+                } else {
+                    Metrics::inc(&self.ctx.broker().metrics.messages_dropped, 1);
+                    debug!("Session[{}] queue full, dropped {}", self.client_id, msg.topic);
+                }
+                return Ok(());
+            }
+            _ => {
+                let Some(packet_id) = self.next_packet_id() else {
+                    let prop = AckProperties::new("no packet identifier available");
+                    return self.disconnect(ReasonCode::QuotaExceeded, prop).await;
+                };
+                self.inflight.insert(packet_id);
+                Some(packet_id)
+            }
+        };
+
+        let publish = Publish {
+            dup: false,
+            qos,
+            retain,
+            topic: msg.topic,
+            packet_id,
+            properties: msg.properties,
+            payload: msg.payload,
+        };
+        self.stream.send(Packet::Publish(publish)).await?;
+        Metrics::inc(&self.ctx.broker().metrics.messages_sent, 1);
+        Ok(())
     }
 
-    async fn run_loop() {}
+    // PUBLISH
+    async fn publish(&mut self, mut publish: Publish) -> Result<(), Error> {
+        let config = self.ctx.config().await;
+        let cfg = config.mqtt;
+        Metrics::inc(&self.ctx.broker().metrics.messages_received, 1);
+
+        if !valid_topic(&publish.topic) {
+            let prop = AckProperties::new(format!("topic name {:?} is invalid", publish.topic))
+                .with("topic", publish.topic);
+            return self.disconnect(ReasonCode::TopicNameInvalid, prop).await;
+        }
+        // Delayed messages are rewritten and authorized by the topic they are delivered to
+        let delay = if config.delayed.enable && publish.topic.starts_with(delayed::PREFIX) {
+            Delayed::parse(&publish.topic).map(|(delay, topic)| (Some(delay), topic.to_string()))
+        } else {
+            Ok((None, publish.topic.clone()))
+        };
+        let delay = match delay {
+            Ok((delay, topic)) => {
+                publish.topic = self.rewrite(Action::Publish, topic, valid_topic).await;
+                Ok(delay)
+            }
+            Err(e) => Err(e),
+        };
+
+        if publish.qos as u8 > cfg.max_qos {
+            let prop = AckProperties::new(format!(
+                "QoS {} exceeds the maximum of {}",
+                publish.qos as u8, cfg.max_qos
+            ))
+            .with("topic", publish.topic);
+            return self.disconnect(ReasonCode::QoSNotSupported, prop).await;
+        }
+
+        if publish.retain && !cfg.retain_available {
+            let prop = AckProperties::new("retained messages are not supported")
+                .with("topic", publish.topic);
+            return self.disconnect(ReasonCode::RetainNotSupported, prop).await;
+        }
+
+        let qos = publish.qos;
+        let packet_id = publish.packet_id.unwrap_or_default();
+
+        // QoS 2 message already received, waiting for PUBREL
+        if qos == QoS::ExactlyOnce && self.awaiting_rel.contains(&packet_id) {
+            let pubrec = PubAck { packet_id, ..Default::default() };
+            return self.stream.send(Packet::PubRec(pubrec)).await;
+        }
+
+        // Payload must be UTF-8 when the Payload Format Indicator is set
+        let utf8 = publish.properties.as_ref().and_then(|prop| prop.payload_format_indicator);
+        let (reason_code, prop) = if utf8 == Some(1)
+            && std::str::from_utf8(&publish.payload).is_err()
+        {
+            let prop =
+                AckProperties::new("payload is not valid UTF-8").with("topic", publish.topic);
+            (ReasonCode::PayloadFormatInvalid, Some(prop))
+        } else if let Err(e) = &delay {
+            let prop = AckProperties::new(e.to_string()).with("topic", publish.topic);
+            (ReasonCode::TopicNameInvalid, Some(prop))
+        } else if publish.topic.starts_with("$SYS/") {
+            let prop = AckProperties::new("$SYS topics are reserved for the broker")
+                .with("topic", publish.topic);
+            (ReasonCode::NotAuthorized, Some(prop))
+        } else if !self.authorize(AclAction::Publish, &publish.topic).await {
+            let prop = AckProperties::new("publish denied by acl").with("topic", publish.topic);
+            if config.acl.deny_action == DenyAction::Disconnect {
+                return self.disconnect(ReasonCode::NotAuthorized, prop).await;
+            }
+            (ReasonCode::NotAuthorized, Some(prop))
+        } else if let Ok(Some(delay)) = delay {
+            let topic = publish.topic.clone();
+            let msg = Message::from_publish(&self.client_id, publish);
+            match self.ctx.delayed().push(msg, delay, config.delayed.max_delayed_messages).await {
+                Ok(_) => (ReasonCode::Success, None),
+                Err(e) => {
+                    let prop = AckProperties::new(e.to_string()).with("topic", topic);
+                    (ReasonCode::QuotaExceeded, Some(prop))
+                }
+            }
+        } else {
+            let topic = publish.topic.clone();
+            match self.ctx.broker().publish(Message::from_publish(&self.client_id, publish)).await {
+                0 => {
+                    let prop =
+                        AckProperties::new("no subscribers matched the topic").with("topic", topic);
+                    (ReasonCode::NoMatchingSubscribers, Some(prop))
+                }
+                _ => (ReasonCode::Success, None),
+            }
+        };
+        let properties = prop.and_then(|prop| self.problem(prop));
+
+        match qos {
+            QoS::AtMostOnce => {}
+            QoS::AtLeastOnce => {
+                let puback = PubAck { packet_id, reason_code: reason_code as u8, properties };
+                self.stream.send(Packet::PubAck(puback)).await?;
+            }
+            QoS::ExactlyOnce => {
+                if (reason_code as u8) < 0x80 {
+                    self.awaiting_rel.insert(packet_id);
+                }
+                let pubrec = PubAck { packet_id, reason_code: reason_code as u8, properties };
+                self.stream.send(Packet::PubRec(pubrec)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // PUBACK, PUBCOMP and a failed PUBREC end a delivery, making room for a queued one
+    async fn release(&mut self, packet_id: u16) -> Result<(), Error> {
+        self.inflight.remove(&packet_id);
+        while self.inflight.len() < self.receive_max as usize {
+            let Some((msg, qos, retain)) = self.pending.pop_front() else {
+                break;
+            };
+            self.send_publish(msg, qos, retain).await?;
+        }
+        Ok(())
+    }
+
+    // PUBREC
+    async fn pubrec(&mut self, pubrec: PubAck) -> Result<(), Error> {
+        let packet_id = pubrec.packet_id;
+        if pubrec.reason_code >= 0x80 {
+            return self.release(packet_id).await;
+        }
+
+        let pubrel = if self.inflight.contains(&packet_id) {
+            PubAck { packet_id, ..Default::default() }
+        } else {
+            let prop = AckProperties::new(format!("packet identifier {} not found", packet_id));
+            PubAck {
+                packet_id,
+                reason_code: ReasonCode::PacketIdentifierNotFound as u8,
+                properties: self.problem(prop),
+            }
+        };
+        self.stream.send(Packet::PubRel(pubrel)).await
+    }
+
+    // PUBREL
+    async fn pubrel(&mut self, pubrel: PubAck) -> Result<(), Error> {
+        let packet_id = pubrel.packet_id;
+        let pubcomp = if self.awaiting_rel.remove(&packet_id) {
+            PubAck { packet_id, ..Default::default() }
+        } else {
+            let prop = AckProperties::new(format!("packet identifier {} not found", packet_id));
+            PubAck {
+                packet_id,
+                reason_code: ReasonCode::PacketIdentifierNotFound as u8,
+                properties: self.problem(prop),
+            }
+        };
+        self.stream.send(Packet::PubComp(pubcomp)).await
+    }
+
+    // Add a subscription to the broker and return the granted QoS,
+    // retained messages to send after the SUBACK are appended to retained
+    async fn install(
+        &mut self,
+        filter: &str,
+        mut options: SubscribeOptions,
+        retained: &mut Vec<(Message, QoS)>,
+    ) -> QoS {
+        // Downgrade to the maximum QoS supported by the server
+        let max_qos = self.ctx.config().await.mqtt.max_qos;
+        options.qos = QoS::try_from(max_qos.min(options.qos as u8)).unwrap_or_default();
+        let existed = self.ctx.broker().subscribe(&self.client_id, filter, options).await;
+
+        // Retain Handling: 0 always send, 1 only for new subscriptions, 2 never send
+        if options.retain_handling == 0 || (options.retain_handling == 1 && !existed) {
+            for msg in self.ctx.broker().retained(filter).await {
+                retained.push((msg, options.qos));
+            }
+        }
+
+        let payload = json!({ "topic": filter, "qos": options.qos as u8 });
+        sys::event(&self.ctx, &self.client_id, "subscribed", payload).await;
+        options.qos
+    }
+
+    // Authorize PUBLISH or SUBSCRIBE, superusers are always allowed
+    async fn authorize(&mut self, action: AclAction, topic: &str) -> bool {
+        if self.grant.is_superuser {
+            return true;
+        }
+        if let Some(permission) = self.acl_cache.get(action, topic) {
+            return permission == Permission::Allow;
+        }
+
+        // Authenticator rules first, then the rules file
+        let cfg = self.ctx.config().await.acl;
+        let client = Client {
+            client_id: &self.client_id,
+            username: self.username.as_deref(),
+            ip: self.stream.addr.ip(),
+        };
+        let permission = match TopicRule::check(&self.grant.acl, action, topic) {
+            Some(permission) => permission,
+            None if cfg.enable => {
+                self.ctx.acl().check(&client, action, topic).unwrap_or(cfg.no_match)
+            }
+            None => Permission::Allow,
+        };
+        if permission == Permission::Deny {
+            debug!("Session[{}] {:?} {} denied by acl", self.client_id, action, topic);
+        }
+
+        self.acl_cache.insert(&cfg, action, topic, permission);
+        permission == Permission::Allow
+    }
+
+    // Install the configured auto subscriptions
+    async fn auto_subscribe(&mut self) -> Result<(), Error> {
+        let mut retained = Vec::new();
+        for auto in self.ctx.config().await.auto_subscribe {
+            let Some(filter) = auto.filter(&self.client_id, self.username.as_deref()) else {
+                continue;
+            };
+            let filter = self.rewrite(Action::Subscribe, filter, valid_filter).await;
+            if !valid_filter(&filter) || filter.starts_with("$share/") {
+                warn!("Session[{}] invalid auto subscription {}", self.client_id, filter);
+                continue;
+            }
+            if !self.authorize(AclAction::Subscribe, &filter).await {
+                warn!("Session[{}] auto subscription {} denied by acl", self.client_id, filter);
+                continue;
+            }
+            self.install(&filter, auto.options(), &mut retained).await;
+        }
+
+        for (msg, qos) in retained {
+            self.send_publish(msg, qos, true).await?;
+        }
+        Ok(())
+    }
+
+    // SUBSCRIBE
+    async fn subscribe(&mut self, subscribe: Subscribe) -> Result<(), Error> {
+        let deny_action = self.ctx.config().await.acl.deny_action;
+        let mut reason_codes = Vec::with_capacity(subscribe.filters.len());
+        let mut prop = AckProperties::default();
+        let mut retained = Vec::new();
+        for (filter, mut options) in subscribe.filters {
+            let filter = self.rewrite(Action::Subscribe, filter, valid_filter).await;
+            let reason_code = if filter.starts_with("$share/") {
+                prop = prop.with(filter, "shared subscriptions are not supported");
+                ReasonCode::SharedSubscriptionsNotSupported as u8
+            } else if !valid_filter(&filter) {
+                prop = prop.with(filter, "topic filter is invalid");
+                ReasonCode::TopicFilterInvalid as u8
+            } else if !self.authorize(AclAction::Subscribe, &filter).await {
+                if deny_action == DenyAction::Disconnect {
+                    let prop = AckProperties::new("subscribe denied by acl").with("topic", filter);
+                    return self.disconnect(ReasonCode::NotAuthorized, prop).await;
+                }
+                prop = prop.with(filter, "subscribe denied by acl");
+                ReasonCode::NotAuthorized as u8
+            } else {
+                options.qos = self.install(&filter, options, &mut retained).await;
+                options.qos as u8
+            };
+            reason_codes.push(reason_code);
+        }
+
+        let failed = prop.user_property.len();
+        let properties = if failed > 0 {
+            prop.reason_string =
+                Some(format!("{} of {} subscriptions failed", failed, reason_codes.len()));
+            self.problem(prop)
+        } else {
+            None
+        };
+
+        let suback = SubAck { packet_id: subscribe.packet_id, properties, reason_codes };
+        self.stream.send(Packet::SubAck(suback)).await?;
+
+        for (msg, qos) in retained {
+            self.send_publish(msg, qos, true).await?;
+        }
+        Ok(())
+    }
+
+    // UNSUBSCRIBE
+    async fn unsubscribe(&mut self, unsubscribe: Unsubscribe) -> Result<(), Error> {
+        let mut reason_codes = Vec::with_capacity(unsubscribe.filters.len());
+        let mut prop = AckProperties::default();
+        for filter in unsubscribe.filters {
+            let filter = self.rewrite(Action::Subscribe, filter, valid_filter).await;
+            if self.ctx.broker().unsubscribe(&self.client_id, &filter).await {
+                let payload = json!({ "topic": filter });
+                sys::event(&self.ctx, &self.client_id, "unsubscribed", payload).await;
+                reason_codes.push(ReasonCode::Success as u8);
+            } else {
+                prop = prop.with(filter, "no subscription existed");
+                reason_codes.push(ReasonCode::NoSubscriptionExisted as u8);
+            }
+        }
+
+        let properties = if prop.user_property.is_empty() { None } else { self.problem(prop) };
+        let unsuback = UnsubAck { packet_id: unsubscribe.packet_id, properties, reason_codes };
+        self.stream.send(Packet::UnsubAck(unsuback)).await
+    }
 }
@@ -0,0 +1,138 @@
+use serde::Deserialize;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Duration;
+
+// TCP socket options of a listener, the OS defaults are kept for options not set
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SocketOptions {
+    // Disable Nagle's algorithm
+    pub nodelay: Option<bool>,
+    // Enable SO_KEEPALIVE with these probe settings
+    pub keepalive: Option<Keepalive>,
+    // SO_SNDBUF and SO_RCVBUF in bytes
+    pub sndbuf: Option<usize>,
+    pub recbuf: Option<usize>,
+    // Pending connections queue length
+    pub backlog: u32,
+    // Let several processes bind the same address, the kernel balances connections between them
+    pub reuse_port: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: None,
+            keepalive: None,
+            sndbuf: None,
+            recbuf: None,
+            backlog: 1024,
+            reuse_port: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Keepalive {
+    // Seconds idle before the first probe
+    pub idle: Option<u64>,
+    // Seconds between probes
+    pub interval: Option<u64>,
+    // Unanswered probes before the connection is dropped
+    pub count: Option<u32>,
+}
+
+impl Keepalive {
+    fn params(&self) -> TcpKeepalive {
+        let mut params = TcpKeepalive::new();
+        if let Some(idle) = self.idle {
+            params = params.with_time(Duration::from_secs(idle));
+        }
+        if let Some(interval) = self.interval {
+            params = params.with_interval(Duration::from_secs(interval));
+        }
+        if let Some(count) = self.count {
+            params = params.with_retries(count);
+        }
+        params
+    }
+}
+
+// Bind a listening socket with the options, accepted sockets inherit most of them
+pub fn bind(addr: SocketAddr, opts: &SocketOptions) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(opts.reuse_port)?;
+    apply(&SockRef::from(&socket), opts)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(opts.backlog.try_into().unwrap_or(i32::MAX))?;
+    TcpListener::from_std(socket.into())
+}
+
+// Set the options on an accepted connection
+pub fn configure(stream: &TcpStream, opts: &SocketOptions) -> io::Result<()> {
+    apply(&SockRef::from(stream), opts)
+}
+
+fn apply(socket: &SockRef<'_>, opts: &SocketOptions) -> io::Result<()> {
+    if let Some(nodelay) = opts.nodelay {
+        socket.set_nodelay(nodelay)?;
+    }
+    if let Some(keepalive) = &opts.keepalive {
+        socket.set_tcp_keepalive(&keepalive.params())?;
+    }
+    if let Some(size) = opts.sndbuf {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = opts.recbuf {
+        socket.set_recv_buffer_size(size)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_options() {
+        let opts = SocketOptions {
+            nodelay: Some(true),
+            keepalive: Some(Keepalive { idle: Some(30), interval: Some(5), count: Some(3) }),
+            sndbuf: Some(64 * 1024),
+            recbuf: Some(32 * 1024),
+            backlog: 16,
+            reuse_port: true,
+        };
+        let listener = bind("127.0.0.1:0".parse().unwrap(), &opts).unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(SockRef::from(&listener).reuse_port().unwrap());
+
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        configure(&stream, &opts).unwrap();
+
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+        assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(5));
+        assert_eq!(socket.keepalive_retries().unwrap(), 3);
+        // Linux doubles the buffer sizes for bookkeeping
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.recv_buffer_size().unwrap() >= 32 * 1024);
+
+        // Options left unset keep the OS defaults
+        let listener = bind("127.0.0.1:0".parse().unwrap(), &SocketOptions::default()).unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        configure(&stream, &SocketOptions::default()).unwrap();
+        assert!(!SockRef::from(&stream).nodelay().unwrap());
+        assert!(!SockRef::from(&stream).keepalive().unwrap());
+    }
+}
@@ -1,49 +1,305 @@
-use crate::protocol::{v3, v5, version, Codec, ConnAck, Error, Packet, Version};
-use crate::Session;
+use crate::auth::{AuthError, ClientInfo, Grant, PeerCert, PeerCred, Step};
+use crate::limiter::RateLimit;
+use crate::metrics::Metrics;
+use crate::protocol::v5::{AckProperties, AuthProperties, ConnAckProperties};
+use crate::protocol::{
+    v3, v5, version, Auth, Codec, ConnAck, Connect, Error, Packet, ReasonCode, Version,
+};
+use crate::{flapping, Context, ListenerConfig, Session};
 use futures::{SinkExt, StreamExt};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{timeout_at, Instant};
 use tokio_util::codec::Framed;
 
 pub struct Stream<S> {
+    ctx: Context,
     io: Framed<S, Codec>,
-    addr: SocketAddr,
-    version: Version,
+    pub addr: SocketAddr,
+    // Name of the listener the client connected to
+    pub listener: String,
+    pub version: Version,
+    // Rate limits applied to packets read once connected
+    limit: RateLimit,
 }
 
 impl<S> Stream<S>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    fn new(io: S, addr: SocketAddr) -> Self {
-        Self { io: Framed::new(io, Codec::Version(version::Codec)), addr, version: Version::V5 }
+    fn new(ctx: Context, io: S, addr: SocketAddr, listener: String) -> Self {
+        let io = Framed::new(io, Codec::Version(version::Codec));
+        Self { ctx, io, addr, listener, version: Version::V5, limit: RateLimit::default() }
     }
 
-    // Handshake
-    pub async fn handshake(io: S, addr: SocketAddr) -> Result<Session, Error> {
-        let mut stream = Self::new(io, addr);
+    // Handshake, from CONNECT through enhanced authentication to CONNACK before the deadline
+    pub async fn handshake(
+        io: S,
+        addr: SocketAddr,
+        ctx: Context,
+        listener: Arc<ListenerConfig>,
+        peer_cert: Option<PeerCert>,
+        peer_cred: Option<PeerCred>,
+        deadline: Instant,
+    ) -> Result<Session<S>, Error> {
+        let connect = Self::connect(io, addr, ctx.clone(), listener, peer_cert, peer_cred);
+        match timeout_at(deadline, connect).await {
+            Ok(session) => session,
+            Err(_) => {
+                Metrics::inc(&ctx.broker().metrics.clients_connect_timeout, 1);
+                Err(Error::ConnectTimeout(addr.to_string()))
+            }
+        }
+    }
+
+    async fn connect(
+        io: S,
+        addr: SocketAddr,
+        ctx: Context,
+        listener: Arc<ListenerConfig>,
+        peer_cert: Option<PeerCert>,
+        peer_cred: Option<PeerCred>,
+    ) -> Result<Session<S>, Error> {
+        let mut stream = Self::new(ctx.clone(), io, addr, listener.name.clone());
+        let cfg = ctx.config().await.mqtt;
+
+        // Get Version and receive Connect Package
+        stream.version = stream.version(cfg.max_packet_size).await?;
+        let mut connect = match stream.recv().await? {
+            (Packet::Connect(connect), _) => connect,
+            _ => return Err(Error::Protocol("the first packet must be CONNECT".into())),
+        };
+
+        // Identity from the client certificate
+        if let Some(cert) = &peer_cert {
+            if let Some(username) = listener.peer_cert_as_username.and_then(|f| cert.get(f)) {
+                connect.username = Some(username.to_string());
+            }
+            if let Some(client_id) = listener.peer_cert_as_clientid.and_then(|f| cert.get(f)) {
+                connect.client_id = client_id.to_string();
+            }
+        }
+
+        // Certificates and peer credentials only stand in for a password when the listener says so
+        let identified = (peer_cert.is_some() && listener.cert_is_identity)
+            || (peer_cred.is_some() && listener.peer_cred_is_identity);
+
+        // Identity from the unix socket peer
+        let peer_cred = peer_cred.filter(|_| listener.peer_cred_as_username);
+        if let Some(cred) = &peer_cred {
+            connect.username = Some(cred.uid.to_string());
+        }
 
-        // Get Version
-        stream.version = stream.version().await?;
+        // Request Problem Information, default 1
+        let problem_info =
+            connect.properties.as_ref().and_then(|prop| prop.request_problem_info) != Some(0);
 
-        // Receive Connect Package
-        let (packet, size) = stream.recv().await?;
-        println!("{:?} {}", packet, size);
+        // Check Client ID
+        let mut assigned_client_id = None;
+        if connect.client_id.is_empty() {
+            if !connect.clean_start {
+                let prop = AckProperties::new("empty client id requires clean start");
+                return stream
+                    .reject(ReasonCode::ClientIdentifierNotValid, prop, problem_info)
+                    .await;
+            }
+            connect.client_id = client_id();
+            assigned_client_id = Some(connect.client_id.clone());
+        } else if connect.client_id.len() > cfg.max_clientid_len as usize {
+            let prop = AckProperties::new(format!(
+                "client id length {} exceeds the maximum of {}",
+                connect.client_id.len(),
+                cfg.max_clientid_len
+            ))
+            .with("max_clientid_len", cfg.max_clientid_len.to_string());
+            return stream.reject(ReasonCode::ClientIdentifierNotValid, prop, problem_info).await;
+        }
+
+        stream.check_banned(&connect, problem_info).await?;
+
+        // Enhanced authentication replaces the authenticator chain
+        let auth_method = connect.properties.as_ref().and_then(|prop| prop.auth_method.clone());
+        let mut auth_data = None;
+        let grant = if let Some(method) = &auth_method {
+            let data = connect.properties.as_mut().and_then(|prop| prop.auth_data.take());
+            let (grant, username, data) =
+                stream.enhanced_auth(method, data.unwrap_or_default(), problem_info).await?;
+            if username.is_some() && username != connect.username {
+                connect.username = username;
+                stream.check_banned(&connect, problem_info).await?;
+            }
+            auth_data = data;
+            grant
+        } else {
+            stream.authenticate(&connect, peer_cred, identified, problem_info).await?
+        };
+
+        // Reconnect storms get the client id banned, only authenticated connections count
+        // so a client that fails authentication cannot get someone else's client id banned
+        if let Some(ban) = flapping::detect(&ctx, &connect.client_id, addr).await {
+            let prop = AckProperties::new(format!("banned: {}", ban.reason));
+            return stream.reject(ReasonCode::Banned, prop, problem_info).await;
+        }
 
-        let packet =
-            Packet::ConnAck(ConnAck { session_present: false, reason_code: 0, properties: None });
+        let properties = ConnAckProperties {
+            assigned_client_identifier: assigned_client_id,
+            auth_method: auth_method.clone(),
+            auth_data,
+            maximum_qos: (cfg.max_qos < 2).then_some(cfg.max_qos),
+            retain_available: (!cfg.retain_available).then_some(0),
+            max_packet_size: Some(cfg.max_packet_size),
+            sub_identifier_available: Some(0),
+            shared_sub_available: Some(0),
+            ..Default::default()
+        };
+        let packet = Packet::ConnAck(ConnAck {
+            session_present: false,
+            reason_code: ReasonCode::Success as u8,
+            properties: Some(properties),
+        });
         stream.send(packet).await?;
 
-        Ok(Session::new())
+        let limiter = ctx.config().await.limiter;
+        stream.limit = RateLimit::new(
+            listener.messages_rate.unwrap_or(limiter.messages_rate),
+            listener.bytes_rate.unwrap_or(limiter.bytes_rate),
+        );
+
+        // Unacknowledged deliveries, the client Receive Maximum if lower than max_receive
+        let receive_max = connect.properties.as_ref().and_then(|prop| prop.receive_max);
+        let receive_max =
+            receive_max.filter(|max| *max > 0).unwrap_or(u16::MAX).min(cfg.max_receive);
+
+        Ok(Session::new(ctx, stream, connect, problem_info, grant, auth_method, receive_max.max(1)))
+    }
+
+    // Reject banned client ids and usernames
+    async fn check_banned(&mut self, connect: &Connect, problem_info: bool) -> Result<(), Error> {
+        let username = connect.username.as_deref();
+        let Some(ban) = self.ctx.banned().identity(&connect.client_id, username) else {
+            return Ok(());
+        };
+        let reason = match ban.reason.as_str() {
+            "" => "banned".to_string(),
+            reason => format!("banned: {}", reason),
+        };
+        let prop = AckProperties::new(reason);
+        self.reject(ReasonCode::Banned, prop, problem_info).await
+    }
+
+    // Authenticate with the authenticator chain
+    async fn authenticate(
+        &mut self,
+        connect: &Connect,
+        peer_cred: Option<PeerCred>,
+        identified: bool,
+        problem_info: bool,
+    ) -> Result<Grant, Error> {
+        let info = ClientInfo {
+            client_id: connect.client_id.clone(),
+            username: connect.username.clone(),
+            password: connect.password.clone(),
+            addr: self.addr,
+            proto_ver: connect.protocol_level.clone(),
+            peer_cred,
+            identified,
+        };
+        let auth = self.ctx.config().await.auth;
+        match self.ctx.auth().authenticate(&auth, &info).await {
+            Ok(grant) => Ok(grant),
+            Err(e) => {
+                let reason_code = match e {
+                    AuthError::Denied(_) => ReasonCode::BadUserNameOrPassword,
+                    AuthError::Anonymous => ReasonCode::NotAuthorized,
+                };
+                let prop = AckProperties::new(e.to_string());
+                self.reject(reason_code, prop, problem_info).await
+            }
+        }
+    }
+
+    // Exchange AUTH packets until the method succeeds or fails,
+    // returns the grant, the authenticated username and the data for the CONNACK
+    async fn enhanced_auth(
+        &mut self,
+        method: &str,
+        mut data: Vec<u8>,
+        problem_info: bool,
+    ) -> Result<(Grant, Option<String>, Option<Vec<u8>>), Error> {
+        let auth = self.ctx.config().await.auth;
+        let Some(mut exchange) = self.ctx.auth().start(&auth, method) else {
+            let prop =
+                AckProperties::new(format!("authentication method {} is not supported", method));
+            return self.reject(ReasonCode::BadAuthenticationMethod, prop, problem_info).await;
+        };
+
+        loop {
+            match exchange.step(&data).await {
+                Step::Continue(challenge) => {
+                    self.send(Packet::Auth(Auth {
+                        reason_code: ReasonCode::ContinueAuthentication as u8,
+                        properties: Some(AuthProperties {
+                            auth_method: Some(method.to_string()),
+                            auth_data: Some(challenge),
+                            ..Default::default()
+                        }),
+                    }))
+                    .await?;
+                }
+                Step::Success { data, username, grant } => return Ok((grant, username, data)),
+                Step::Failure(reason) => {
+                    let prop = AckProperties::new(reason);
+                    return self.reject(ReasonCode::NotAuthorized, prop, problem_info).await;
+                }
+            }
+
+            let prop = match self.recv().await? {
+                (Packet::Auth(auth), _)
+                    if auth.reason_code == ReasonCode::ContinueAuthentication as u8 =>
+                {
+                    auth.properties.unwrap_or_default()
+                }
+                (Packet::Disconnect(_), _) => return Err(Error::Disconnect(self.addr.to_string())),
+                (packet, _) => {
+                    let prop = AckProperties::new(format!("unexpected {:?} packet", packet));
+                    return self.reject(ReasonCode::ProtocolError, prop, problem_info).await;
+                }
+            };
+            if prop.auth_method.as_deref() != Some(method) {
+                let prop = AckProperties::new("authentication method changed");
+                return self.reject(ReasonCode::ProtocolError, prop, problem_info).await;
+            }
+            data = prop.auth_data.unwrap_or_default();
+        }
+    }
+
+    // Reject the connection with a CONNACK carrying the reason
+    async fn reject<T>(
+        &mut self,
+        reason_code: ReasonCode,
+        prop: AckProperties,
+        problem_info: bool,
+    ) -> Result<T, Error> {
+        let reason = prop.reason_string.clone().unwrap_or_default();
+        let packet = Packet::ConnAck(ConnAck {
+            session_present: false,
+            reason_code: reason_code as u8,
+            properties: problem_info.then(|| prop.into()),
+        });
+        self.send(packet).await?;
+        Err(Error::Protocol(format!("{:?}: {}", reason_code, reason)))
     }
 
     // Get Version
-    async fn version(&mut self) -> Result<Version, Error> {
+    async fn version(&mut self, max_packet_size: u32) -> Result<Version, Error> {
         match self.recv().await? {
             (Packet::Version(version), _) => {
                 match version {
-                    Version::V3 => *self.io.codec_mut() = Codec::V3(v3::Codec),
-                    Version::V5 => *self.io.codec_mut() = Codec::V5(v5::Codec),
+                    Version::V3 => *self.io.codec_mut() = Codec::V3(v3::Codec { max_packet_size }),
+                    Version::V5 => *self.io.codec_mut() = Codec::V5(v5::Codec { max_packet_size }),
                 }
                 Ok(version)
             }
@@ -51,17 +307,44 @@ where
         }
     }
 
+    // Wait while the client is over its rate limits, the unread data stays in the socket
+    pub async fn throttle(&mut self) {
+        self.limit.wait().await;
+    }
+
+    // Time the rate limits pause reading until
+    pub fn paused_until(&self) -> Option<Instant> {
+        self.limit.resume_at()
+    }
+
     // Receive Packet
-    async fn recv(&mut self) -> Result<(Packet, u32), Error> {
+    pub async fn recv(&mut self) -> Result<(Packet, u32), Error> {
         match self.io.next().await {
-            Some(Ok(packet)) => Ok(packet),
+            Some(Ok((packet, size))) => {
+                Metrics::inc(&self.ctx.broker().metrics.bytes_received, size as u64);
+                self.limit.consume(matches!(packet, Packet::Publish(_)) as u32, size);
+                Ok((packet, size))
+            }
             Some(Err(e)) => Err(e),
             None => Err(Error::Disconnect(self.addr.to_string())),
         }
     }
 
     // Send Packet
-    async fn send(&mut self, packet: Packet) -> Result<(), Error> {
-        self.io.send(packet).await
+    pub async fn send(&mut self, packet: Packet) -> Result<(), Error> {
+        // The write buffer is flushed after every packet, so it only holds the encoded packet
+        self.io.feed(packet).await?;
+        let size = self.io.write_buffer().len();
+        self.io.flush().await?;
+        Metrics::inc(&self.ctx.broker().metrics.bytes_sent, size as u64);
+        Ok(())
     }
 }
+
+// Generate a client id for clients connecting with an empty one
+fn client_id() -> String {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{:x}{:04x}", env!("CARGO_PKG_NAME"), nanos, seq & 0xFFFF)
+}
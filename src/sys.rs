@@ -0,0 +1,101 @@
+use crate::broker::{valid_level, Message};
+use crate::Context;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+use tracing::{debug, info};
+
+// $SYS configuration
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Sys {
+    // Publish interval in seconds, 0 disables the broker stats
+    pub interval: u64,
+    // Publish client connected, disconnected, subscribed and unsubscribed events
+    pub events: bool,
+}
+
+impl Default for Sys {
+    fn default() -> Self {
+        Self { interval: 60, events: true }
+    }
+}
+
+// Topic prefix of this node
+async fn prefix(ctx: &Context) -> String {
+    format!("$SYS/brokers/{}", ctx.config().await.node.name)
+}
+
+// Periodically publish broker stats
+pub async fn run(ctx: Context) {
+    let cfg = ctx.config().await.sys;
+    let prefix = prefix(&ctx).await;
+    let broker = ctx.broker();
+
+    let mut version = Message::new(format!("{}/version", prefix), env!("CARGO_PKG_VERSION"));
+    version.retain = true;
+    broker.publish(version).await;
+
+    if cfg.interval == 0 {
+        return;
+    }
+
+    info!("$SYS publishing every {}s", cfg.interval);
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(cfg.interval));
+    let mut shutdown = ctx.subscribe();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let metrics = broker.metrics.snapshot();
+                let stats = [
+                    ("uptime", metrics.uptime),
+                    ("stats/connections/count", broker.session_count().await as u64),
+                    ("stats/subscriptions/count", broker.subscription_count().await as u64),
+                    ("stats/retained/count", broker.retained_count().await as u64),
+                    ("metrics/clients/connected", metrics.clients_connected),
+                    ("metrics/clients/disconnected", metrics.clients_disconnected),
+                    ("metrics/clients/connect_timeout", metrics.clients_connect_timeout),
+                    ("metrics/messages/received", metrics.messages_received),
+                    ("metrics/messages/sent", metrics.messages_sent),
+                    ("metrics/messages/dropped", metrics.messages_dropped),
+                    ("metrics/bytes/received", metrics.bytes_received),
+                    ("metrics/bytes/sent", metrics.bytes_sent),
+                ];
+                for (topic, value) in stats {
+                    let msg = Message::new(format!("{}/{}", prefix, topic), value.to_string());
+                    broker.publish(msg).await;
+                }
+                for listener in ctx.connections().stats() {
+                    let topic = format!("{}/stats/listeners/{}/connections", prefix, listener.name);
+                    let counts = [
+                        ("count", listener.current_connections),
+                        ("max", listener.max_connections),
+                    ];
+                    for (name, value) in counts {
+                        let msg = Message::new(format!("{}/{}", topic, name), value.to_string());
+                        broker.publish(msg).await;
+                    }
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+// Publish a client event to $SYS/brokers/<node>/clients/<clientid>/<event>
+pub async fn event(ctx: &Context, client_id: &str, event: &str, mut payload: Value) {
+    if !ctx.config().await.sys.events {
+        return;
+    }
+    // The client id is a topic level, one holding wildcards or separators cannot be published
+    if !valid_level(client_id) {
+        debug!("No {} event for client {:?}, its id is not a valid topic level", event, client_id);
+        return;
+    }
+
+    payload["clientid"] = client_id.into();
+    payload["ts"] = chrono::Local::now().timestamp_millis().into();
+    let topic = format!("{}/clients/{}/{}", prefix(ctx).await, client_id, event);
+    ctx.broker().publish(Message::new(topic, payload.to_string())).await;
+}
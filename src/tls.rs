@@ -0,0 +1,132 @@
+use crate::config::TlsVersion;
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::{aws_lc_rs, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{version, SupportedProtocolVersion};
+
+// Crypto provider offering only the named cipher suites, all of them if none are named
+pub fn provider(ciphers: &[String]) -> Result<CryptoProvider, anyhow::Error> {
+    let mut provider = CryptoProvider::get_default()
+        .map(|provider| provider.as_ref().clone())
+        .unwrap_or_else(aws_lc_rs::default_provider);
+    if ciphers.is_empty() {
+        return Ok(provider);
+    }
+
+    let supported = std::mem::take(&mut provider.cipher_suites);
+    for name in ciphers {
+        let suite = supported
+            .iter()
+            .find(|suite| suite.suite().as_str() == Some(name.as_str()))
+            .ok_or(anyhow!("unsupported cipher suite {}", name))?;
+        provider.cipher_suites.push(*suite);
+    }
+    Ok(provider)
+}
+
+static TLS12_UP: &[&SupportedProtocolVersion] = &[&version::TLS13, &version::TLS12];
+static TLS13_UP: &[&SupportedProtocolVersion] = &[&version::TLS13];
+
+// TLS versions from the minimum up
+pub fn versions(min: Option<TlsVersion>) -> &'static [&'static SupportedProtocolVersion] {
+    match min {
+        Some(TlsVersion::Tls13) => TLS13_UP,
+        Some(TlsVersion::Tls12) | None => TLS12_UP,
+    }
+}
+
+// Certificate chain and private key from PEM files
+pub fn certified_key(
+    cert_file: &str,
+    key_file: &str,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, anyhow::Error> {
+    let key = PrivateKeyDer::from_pem_file(key_file)
+        .map_err(|e| anyhow!("key[{:?}]: {:?}", key_file, e))?;
+    let certs = CertificateDer::pem_file_iter(cert_file)
+        .map_err(|e| anyhow!("cert[{:?}]: {:?}", cert_file, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("cert[{:?}]: {:?}", cert_file, e))?;
+    CertifiedKey::from_der(certs, key, provider)
+        .map_err(|e| anyhow!("cert[{:?}] key[{:?}]: {}", cert_file, key_file, e))
+}
+
+// Picks the certificate by the SNI hostname, "*.example.com" matches one label,
+// the default one is served to clients without SNI or asking for another name
+#[derive(Debug)]
+pub struct SniResolver {
+    default: Option<Arc<CertifiedKey>>,
+    names: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    pub fn new(default: Option<CertifiedKey>) -> Self {
+        Self { default: default.map(Arc::new), names: HashMap::new() }
+    }
+
+    pub fn add(&mut self, server_name: &str, key: CertifiedKey) {
+        self.names.insert(server_name.to_ascii_lowercase(), Arc::new(key));
+    }
+
+    fn lookup(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let found = server_name.map(str::to_ascii_lowercase).and_then(|name| {
+            let wildcard = name.split_once('.').map(|(_, parent)| format!("*.{}", parent));
+            self.names.get(&name).or_else(|| wildcard.and_then(|w| self.names.get(&w)))
+        });
+        found.or(self.default.as_ref()).cloned()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.lookup(client_hello.server_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, KeyPair};
+
+    fn key(name: &str) -> CertifiedKey {
+        let key_pair = KeyPair::generate().unwrap();
+        let cert =
+            CertificateParams::new(vec![name.into()]).unwrap().self_signed(&key_pair).unwrap();
+        let key = PrivateKeyDer::try_from(key_pair.serialize_der()).unwrap();
+        CertifiedKey::from_der(vec![cert.der().clone()], key, &aws_lc_rs::default_provider())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sni_resolver() {
+        let mut resolver = SniResolver::new(Some(key("default")));
+        resolver.add("a.example.com", key("a.example.com"));
+        resolver.add("*.example.org", key("*.example.org"));
+        let served = |name, expected: &Arc<CertifiedKey>| {
+            resolver.lookup(name).is_some_and(|key| Arc::ptr_eq(&key, expected))
+        };
+        let default = resolver.default.as_ref().unwrap();
+
+        assert!(served(Some("A.example.com"), &resolver.names["a.example.com"]));
+        assert!(served(Some("b.example.org"), &resolver.names["*.example.org"]));
+        // Wildcards cover a single label
+        assert!(served(Some("c.b.example.org"), default));
+        assert!(served(Some("b.example.com"), default));
+        assert!(served(None, default));
+
+        assert!(SniResolver::new(None).lookup(Some("a.example.com")).is_none());
+    }
+
+    #[test]
+    fn test_provider() {
+        assert!(!provider(&[]).unwrap().cipher_suites.is_empty());
+        let suites = provider(&["TLS13_AES_256_GCM_SHA384".into()]).unwrap().cipher_suites;
+        assert_eq!(suites.len(), 1);
+        assert!(provider(&["TLS_NULL_WITH_NULL_NULL".into()]).is_err());
+    }
+}
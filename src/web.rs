@@ -3,6 +3,7 @@ use crate::Context;
 use crate::Error;
 use axum::{Extension, Router};
 use serde::Deserialize;
+use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tower_http::services::{ServeDir, ServeFile};
 use tracing::info;
@@ -12,6 +13,9 @@ use tracing::info;
 pub struct Web {
     #[serde(default = "Web::default_addr")]
     pub addr: String,
+    // Bearer token the admin api requires, without one it only answers loopback clients
+    #[serde(default)]
+    pub api_key: Option<String>,
 }
 
 impl Web {
@@ -42,7 +46,8 @@ impl WebServer {
         info!("WebServer[{}] starting...", config.addr);
 
         let ln = TcpListener::bind(config.addr).await?;
-        axum::serve(ln, Self::routes(ctx.clone()))
+        let app = Self::routes(ctx.clone()).into_make_service_with_connect_info::<SocketAddr>();
+        axum::serve(ln, app)
             .with_graceful_shutdown(async move {
                 let _ = ctx.subscribe().recv().await;
             })